@@ -1,72 +1,171 @@
+use crate::buffer::{Buffer, MergeState};
+use crate::config::{self, Autosave};
 use crate::editor::Editor;
+use crate::file_format;
+use crate::find::{FindBar, FindField};
+use crate::grep::{Grep, GrepMatch, Preview};
+use crate::keymap::Action;
+use crate::layout::{self, Node, Pane, PaneId, Side};
+use crate::merge::{Choice, Merge, Resolution};
+use crate::picker::{Picker, PickerKind};
+use crate::replace::ReplaceUndo;
+use crate::save::{self, BackupMode};
 use crate::search::FileSearch;
+use crate::swap::{self, DiffLine, Swap};
+use crate::syntax;
+use crate::theme::{self, Palette, Themes};
 use crate::ui;
-use anyhow::Result;
+use crate::undo::UndoTree;
+use crate::viewport::Viewport;
+use anyhow::{bail, Result};
 use crossterm::event::{self, Event, KeyCode, KeyModifiers, MouseEventKind};
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+use syntect::parsing::SyntaxSet;
 use ratatui::prelude::*;
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Component, Path, PathBuf};
-use std::sync::mpsc::{self, Receiver, TryRecvError};
-use std::thread;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Mode {
     Search,
+    Grep,
     Edit,
+    Merge,
+}
+
+/// What the path typed into the create prompt is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreateKind {
+    /// A new file, or a folder if it ends in `/`
+    New,
+    /// The name to save the active buffer under
+    SaveAs,
+}
+
+/// Pending offer to restore unsaved edits from a swap file.
+pub struct Recovery {
+    pub swap: Swap,
+    pub diff: Vec<DiffLine>,
+    pub scroll: usize,
 }
 
 pub struct App {
     pub mode: Mode,
     pub search: FileSearch,
-    pub editor: Option<Editor>,
+    pub grep: Grep,
+    /// Last project-wide replace, for Ctrl+U in grep mode
+    last_replace: Option<ReplaceUndo>,
+    /// Open files, shown through `panes`
+    pub buffers: Vec<Buffer>,
+    next_buffer_id: usize,
+    pub panes: HashMap<PaneId, Pane>,
+    pub layout: Node,
+    /// Pane receiving input; its buffer is the active one
+    pub focused: PaneId,
+    next_pane_id: PaneId,
+    /// Where each pane was last drawn, for mouse focus and neighbour lookup
+    pub pane_areas: Vec<(PaneId, Rect)>,
     pub search_input: String,
     pub selected_index: usize,
     pub should_quit: bool,
     pub status_message: Option<String>,
     pub list_area: Rect,
-    pub file_changed_externally: bool,
-    _watcher: Option<RecommendedWatcher>,
-    watcher_rx: Option<Receiver<PathBuf>>,
     _root_watcher: Option<RecommendedWatcher>,
     root_watcher_rx: Option<Receiver<()>>,
-    index_rx: Option<Receiver<Result<Vec<PathBuf>>>>,
     pub create_active: bool,
+    pub create_kind: CreateKind,
     pub create_input: String,
     pub create_base: PathBuf,
+    /// Asking whether to save or discard unsaved buffers before quitting
+    pub quit_prompt: bool,
+    /// Undo histories of files closed this session, reattached on reopen.
+    undo_histories: HashMap<PathBuf, UndoTree>,
+    backup_mode: BackupMode,
+    /// Settings from the config files, reloaded with F5
+    pub config: config::Config,
+    /// Tab stop width given to every buffer opened
+    tab_width: usize,
+    pub recovery: Option<Recovery>,
+    pub picker: Option<Picker>,
+    /// Bundled and user syntax definitions shared by every buffer
+    syntax_set: Arc<SyntaxSet>,
+    themes: Themes,
+    /// Chosen syntax theme; the theme picker previews others without
+    /// changing it
+    pub theme: String,
+    /// UI colours matching the theme on screen
+    pub palette: Palette,
+    /// In-buffer find bar; takes the keyboard while open
+    pub find: Option<FindBar>,
+    /// Show match scores next to file search results
+    pub show_scores: bool,
 }
 
 impl App {
     pub fn new(root: PathBuf) -> Result<Self> {
-        let search = FileSearch::new_deferred(root.clone())?;
-        let (tx, rx) = mpsc::channel();
-        let root_clone = root.clone();
-        let show_hidden = search.show_hidden;
-        thread::spawn(move || {
-            let result = FileSearch::collect_files(&root_clone, show_hidden);
-            let _ = tx.send(result);
-        });
+        // A broken config still starts the editor, with the defaults
+        let (config, config_error) = match config::load(&root) {
+            Ok(config) => (config, None),
+            Err(e) => (config::Config::default(), Some(e)),
+        };
+        let search = FileSearch::new_deferred(root.clone(), config.show_hidden)?;
+
+        let (syntax_set, syntax_errors) = syntax::load();
+        let themes = Themes::load();
+        let theme = theme::initial_name(&themes, config.theme.as_deref());
+        let mut palette = themes.get(&theme).map(|theme| Palette::from_theme(theme)).unwrap_or_default();
+        config.colors.apply(&mut palette);
 
         let mut app = Self {
             mode: Mode::Search,
             search,
-            editor: None,
+            grep: Grep::new(),
+            last_replace: None,
+            buffers: Vec::new(),
+            next_buffer_id: 0,
+            panes: HashMap::new(),
+            layout: Node::Leaf(0),
+            focused: 0,
+            next_pane_id: 1,
+            pane_areas: Vec::new(),
             search_input: String::new(),
             selected_index: 0,
             should_quit: false,
             list_area: Rect::default(),
-            file_changed_externally: false,
-            _watcher: None,
-            watcher_rx: None,
             _root_watcher: None,
             root_watcher_rx: None,
-            index_rx: Some(rx),
             create_active: false,
+            create_kind: CreateKind::New,
             create_input: String::new(),
             create_base: PathBuf::new(),
+            quit_prompt: false,
+            undo_histories: HashMap::new(),
+            backup_mode: BackupMode::from_env().unwrap_or(config.backup),
+            tab_width: tab_width_from_env().unwrap_or(config.tab_width),
+            config,
+            recovery: None,
+            picker: None,
+            syntax_set: Arc::new(syntax_set),
+            themes,
+            theme,
+            palette,
+            find: None,
+            show_scores: false,
             status_message: Some("Indexing...".to_string()),
         };
+        if let Some(error) = app.themes.errors.first() {
+            app.status_message = Some(format!("Theme failed to load: {}", error));
+        }
+        if let Some(error) = syntax_errors.first() {
+            app.status_message = Some(format!("Syntax failed to load: {}", error));
+        }
+        if let Some(e) = config_error {
+            app.status_message = Some(format!("Config: {:#}", e));
+        }
         let root = app.search.root.clone();
         if let Err(e) = app.setup_root_watcher(&root) {
             app.status_message = Some(format!("Root watcher failed: {}", e));
@@ -74,33 +173,26 @@ impl App {
         Ok(app)
     }
 
-    fn setup_watcher(&mut self, path: &PathBuf) -> Result<()> {
-        let (tx, rx) = mpsc::channel();
-        let path_clone = path.clone();
+    /// Index into `buffers` of the focused pane's buffer.
+    pub fn active_index(&self) -> Option<usize> {
+        let id = self.panes.get(&self.focused)?.buffer;
+        self.buffers.iter().position(|b| b.id == id)
+    }
 
-        let mut watcher = RecommendedWatcher::new(
-            move |res: notify::Result<notify::Event>| {
-                if let Ok(event) = res {
-                    // Check for any data modification events
-                    use notify::EventKind;
-                    match event.kind {
-                        EventKind::Modify(_) | EventKind::Create(_) => {
-                            let _ = tx.send(path_clone.clone());
-                        }
-                        _ => {}
-                    }
-                }
-            },
-            Config::default(),
-        )?;
+    pub fn active_buffer(&self) -> Option<&Buffer> {
+        self.active_index().map(|idx| &self.buffers[idx])
+    }
 
-        watcher.watch(path, RecursiveMode::NonRecursive)?;
-        self._watcher = Some(watcher);
-        self.watcher_rx = Some(rx);
-        Ok(())
+    fn active_buffer_mut(&mut self) -> Option<&mut Buffer> {
+        let idx = self.active_index()?;
+        self.buffers.get_mut(idx)
+    }
+
+    pub fn active_editor(&self) -> Option<&Editor> {
+        self.active_buffer().map(|b| &b.editor)
     }
 
-    fn setup_root_watcher(&mut self, path: &PathBuf) -> Result<()> {
+    fn setup_root_watcher(&mut self, path: &Path) -> Result<()> {
         let (tx, rx) = mpsc::channel();
 
         let mut watcher = RecommendedWatcher::new(
@@ -128,45 +220,146 @@ impl App {
         Ok(())
     }
 
-    fn clear_watcher(&mut self) {
-        self._watcher = None;
-        self.watcher_rx = None;
-        self.file_changed_externally = false;
-    }
+    /// Handle pending watcher events for every open buffer. Returns true
+    /// if anything visible changed.
+    fn check_file_changes(&mut self) -> bool {
+        let mut any_changed = false;
+        let debounce = self.config.watch_debounce;
+        for idx in 0..self.buffers.len() {
+            let buffer = &mut self.buffers[idx];
+            if !buffer.poll_changes(debounce) {
+                continue;
+            }
+            let theirs = match read_disk_lines(&buffer.editor.path) {
+                Ok(lines) => lines,
+                // Mid-write or deleted; a later event will follow
+                Err(_) => continue,
+            };
+            // Our own save, or a touch that didn't change the text
+            if buffer.editor.is_base(&theirs) {
+                continue;
+            }
 
-    fn check_file_changes(&mut self) {
-        if self.editor.is_none() {
-            return;
+            any_changed = true;
+            if buffer.editor.is_modified() {
+                buffer.file_changed_externally = true;
+                self.start_merge(idx, theirs);
+            } else {
+                let name = buffer.editor.filename();
+                match buffer.editor.reload() {
+                    Ok(()) => {
+                        buffer.file_changed_externally = false;
+                        self.status_message = Some(format!("{} reloaded (external change)", name));
+                    }
+                    Err(e) => {
+                        self.status_message = Some(format!("Reload of {} failed: {}", name, e));
+                    }
+                }
+            }
         }
+        any_changed
+    }
 
-        let mut changed = false;
-        if let Some(ref rx) = self.watcher_rx {
-            // Non-blocking check for file change events
-            while rx.try_recv().is_ok() {
-                changed = true;
+    /// Three-way merge buffer `idx` with the disk version `theirs`. Clean
+    /// merges are applied straight away; otherwise the conflict is kept on
+    /// the buffer and shown if it is the one being edited.
+    fn start_merge(&mut self, idx: usize, theirs: Vec<String>) {
+        let active = self.active_index();
+        let Some(buffer) = self.buffers.get_mut(idx) else {
+            return;
+        };
+        let name = buffer.editor.filename();
+        let merge = Merge::new(
+            &buffer.editor.base_lines(),
+            &buffer.editor.lines(),
+            &theirs,
+        );
+        if let Some(result) = merge.result() {
+            buffer.editor.apply_merge(theirs, result);
+            buffer.file_changed_externally = false;
+            buffer.merge = None;
+            if Some(idx) == active && self.mode == Mode::Merge {
+                self.mode = Mode::Edit;
             }
+            self.status_message = Some(format!("External changes merged into {}", name));
+            return;
         }
 
-        if !changed {
-            return;
+        let conflicts = merge.unresolved();
+        buffer.merge = Some(MergeState {
+            merge,
+            selected: 0,
+        });
+        if Some(idx) == active && self.mode == Mode::Edit {
+            self.mode = Mode::Merge;
         }
+        self.status_message = Some(format!(
+            "External change to {}: {} conflicting hunk{}",
+            name,
+            conflicts,
+            if conflicts == 1 { "" } else { "s" }
+        ));
+    }
 
-        if let Some(ref mut editor) = self.editor {
-            if editor.is_modified() {
-                self.file_changed_externally = true;
-                self.status_message = Some("External change detected (unsaved edits)".to_string());
-            } else {
-                match editor.reload() {
-                    Ok(()) => {
-                        self.file_changed_externally = false;
-                        self.status_message = Some("File reloaded (external change)".to_string());
-                    }
-                    Err(e) => {
-                        self.status_message = Some(format!("Reload failed: {}", e));
+    fn handle_merge_key(&mut self, code: KeyCode) -> Result<()> {
+        self.status_message = None;
+        let Some(buffer) = self.active_buffer_mut() else {
+            return Ok(());
+        };
+        let Some(ref mut state) = buffer.merge else {
+            self.mode = Mode::Edit;
+            return Ok(());
+        };
+        let conflicts = state.merge.conflicts();
+        let current = conflicts.get(state.selected).copied();
+        match code {
+            KeyCode::Esc => {
+                // Back to the buffer; the conflict stays flagged
+                self.mode = Mode::Edit;
+            }
+            KeyCode::Down | KeyCode::Char('n') => {
+                state.selected = (state.selected + 1).min(conflicts.len().saturating_sub(1));
+            }
+            KeyCode::Up | KeyCode::Char('p') => {
+                state.selected = state.selected.saturating_sub(1);
+            }
+            KeyCode::Char(c @ ('o' | 't' | 'b' | 'a')) => {
+                let choice = match c {
+                    'o' => Choice::Ours,
+                    't' => Choice::Theirs,
+                    'b' => Choice::Both,
+                    _ => Choice::Base,
+                };
+                if let Some(hunk) = current {
+                    state.merge.choose(hunk, choice);
+                    // Move on to the next unresolved conflict
+                    if let Some(next) = conflicts
+                        .iter()
+                        .position(|&h| state.merge.hunks[h].resolution == Resolution::Conflict(None))
+                    {
+                        state.selected = next;
                     }
                 }
             }
+            KeyCode::Enter => match state.merge.result() {
+                Some(result) => {
+                    let theirs = state.merge.theirs.clone();
+                    buffer.editor.apply_merge(theirs, result);
+                    buffer.merge = None;
+                    buffer.file_changed_externally = false;
+                    self.mode = Mode::Edit;
+                    self.status_message = Some("Merge applied".to_string());
+                }
+                None => {
+                    self.status_message = Some(format!(
+                        "{} conflict(s) still unresolved",
+                        state.merge.unresolved()
+                    ));
+                }
+            },
+            _ => {}
         }
+        Ok(())
     }
 
     fn check_root_changes(&mut self) -> bool {
@@ -191,48 +384,28 @@ impl App {
         }
     }
 
-    fn check_indexing(&mut self) -> bool {
-        let Some(ref rx) = self.index_rx else {
+    /// Take in new file matches, keeping the selection on the list.
+    fn tick_search(&mut self) -> bool {
+        let was_indexing = self.search.indexing;
+        if !self.search.tick() {
             return false;
-        };
-
-        match rx.try_recv() {
-            Ok(result) => {
-                self.index_rx = None;
-                match result {
-                    Ok(files) => {
-                        self.search.apply_index(files);
-                        self.search.update_query(&self.search_input);
-                        let max = self.search.match_count().saturating_sub(1);
-                        if self.selected_index > max {
-                            self.selected_index = max;
-                        }
-                        if matches!(self.status_message.as_deref(), Some("Indexing...")) {
-                            self.status_message = None;
-                        }
-                    }
-                    Err(e) => {
-                        self.search.indexing = false;
-                        self.status_message = Some(format!("Indexing failed: {}", e));
-                    }
-                }
-                true
-            }
-            Err(TryRecvError::Empty) => false,
-            Err(TryRecvError::Disconnected) => {
-                self.index_rx = None;
-                self.search.indexing = false;
-                self.status_message = Some("Indexing failed: worker disconnected".to_string());
-                true
-            }
         }
+        let max = self.search.match_count().saturating_sub(1);
+        if self.selected_index > max {
+            self.selected_index = max;
+        }
+        if was_indexing && !self.search.indexing && matches!(self.status_message.as_deref(), Some("Indexing...")) {
+            self.status_message = None;
+        }
+        true
     }
 
     pub fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
         let tick_rate = Duration::from_millis(100);
-        let refresh_interval = Duration::from_millis(300);
         let mut last_tick = Instant::now();
         let mut last_root_refresh = Instant::now();
+        let mut last_swap = Instant::now();
+        let mut last_clock = Instant::now();
         let mut should_draw = true;
         let mut root_refresh_pending = false;
 
@@ -254,6 +427,9 @@ impl App {
                     Event::Mouse(mouse) => {
                         self.handle_mouse(mouse.kind, mouse.column, mouse.row)?;
                     }
+                    Event::FocusLost if self.config.autosave == Autosave::FocusLost => {
+                        self.autosave(true);
+                    }
                     Event::Resize(_, _) => {}
                     _ => {}
                 }
@@ -261,21 +437,22 @@ impl App {
             }
 
             if last_tick.elapsed() >= tick_rate {
-                let before = self.file_changed_externally;
-                self.check_file_changes();
-                if self.file_changed_externally != before {
+                if self.check_file_changes() {
                     should_draw = true;
                 }
 
                 if self.check_root_changes() {
                     root_refresh_pending = true;
                 }
-                if self.check_indexing() {
+                if self.tick_search() {
+                    should_draw = true;
+                }
+                if self.grep.poll() && self.mode == Mode::Grep {
                     should_draw = true;
                 }
                 if root_refresh_pending
                     && !self.search.indexing
-                    && last_root_refresh.elapsed() >= refresh_interval
+                    && last_root_refresh.elapsed() >= self.config.watch_debounce
                 {
                     self.refresh_search();
                     root_refresh_pending = false;
@@ -284,16 +461,59 @@ impl App {
                         should_draw = true;
                     }
                 }
+                if self.config.autosave == Autosave::Idle && self.autosave(false) {
+                    should_draw = true;
+                }
+                // Keeps "saved 10s ago" in the status bar current
+                if self.mode == Mode::Edit && last_clock.elapsed() >= Duration::from_secs(1) {
+                    should_draw = true;
+                    last_clock = Instant::now();
+                }
+                if last_swap.elapsed() >= swap::SWAP_INTERVAL {
+                    self.write_swaps();
+                    last_swap = Instant::now();
+                }
                 last_tick = Instant::now();
             }
         }
+
+        // Clean exit: keep undo history, drop swaps that match the disk
+        for buffer in &mut self.buffers {
+            if !buffer.editor.is_modified() {
+                buffer.editor.remove_swap();
+            }
+            let _ = buffer.editor.persist_history();
+        }
+        swap::remove_stale();
         Ok(())
     }
 
+    fn write_swaps(&mut self) {
+        for buffer in &mut self.buffers {
+            if let Err(e) = buffer.editor.write_swap() {
+                self.status_message = Some(format!("Swap write failed: {}", e));
+            }
+        }
+    }
+
     fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> Result<()> {
+        if self.recovery.is_some() {
+            self.handle_recovery_key(code);
+            return Ok(());
+        }
+        if self.quit_prompt {
+            self.handle_quit_key(code);
+            return Ok(());
+        }
+        if self.picker.is_some() {
+            self.handle_picker_key(code);
+            return Ok(());
+        }
         match self.mode {
             Mode::Search => self.handle_search_key(code, modifiers),
+            Mode::Grep => self.handle_grep_key(code, modifiers),
             Mode::Edit => self.handle_edit_key(code, modifiers),
+            Mode::Merge => self.handle_merge_key(code),
         }
     }
 
@@ -315,42 +535,84 @@ impl App {
                         let max = self.search.match_count().saturating_sub(1);
                         self.selected_index = (self.selected_index + 3).min(max);
                     }
-                    MouseEventKind::Down(_) => {
-                        // Check if click is in list area
+                    // Check if click is in list area
+                    MouseEventKind::Down(_)
                         if col >= self.list_area.x
                             && col < self.list_area.x + self.list_area.width
-                            && row >= self.list_area.y + 1  // +1 for border
-                            && row < self.list_area.y + self.list_area.height - 1
-                        {
-                            let list_height = self.list_area.height.saturating_sub(2) as usize;
-                            let scroll_offset = if self.selected_index >= list_height {
-                                self.selected_index - list_height + 1
-                            } else {
-                                0
-                            };
-
-                            let clicked_row = (row - self.list_area.y - 1) as usize;
-                            let clicked_index = scroll_offset + clicked_row;
-
-                            if clicked_index < self.search.match_count() {
-                                self.selected_index = clicked_index;
-                                // Also trigger action (toggle folder or open file)
-                                self.handle_enter()?;
-                            }
+                            && row > self.list_area.y // +1 for border
+                            && row < self.list_area.y + self.list_area.height - 1 =>
+                    {
+                        let list_height = self.list_area.height.saturating_sub(2) as usize;
+                        let scroll_offset = if self.selected_index >= list_height {
+                            self.selected_index - list_height + 1
+                        } else {
+                            0
+                        };
+
+                        let clicked_row = (row - self.list_area.y - 1) as usize;
+                        let clicked_index = scroll_offset + clicked_row;
+
+                        if clicked_index < self.search.match_count() {
+                            self.selected_index = clicked_index;
+                            // Also trigger action (toggle folder or open file)
+                            self.handle_enter()?;
                         }
                     }
                     _ => {}
                 }
             }
             Mode::Edit => {
-                if let Some(ref mut editor) = self.editor {
-                    match kind {
-                        MouseEventKind::ScrollUp => editor.scroll_up(3),
-                        MouseEventKind::ScrollDown => editor.scroll_down(3),
-                        _ => {}
+                let pos = Position::new(col, row);
+                let hovered = self
+                    .pane_areas
+                    .iter()
+                    .find(|(_, area)| area.contains(pos))
+                    .map(|(id, _)| *id);
+                let (rows, cells) = match kind {
+                    MouseEventKind::Down(_) => {
+                        if let Some(pane) = hovered {
+                            self.focus_pane(pane);
+                        }
+                        return Ok(());
                     }
+                    MouseEventKind::ScrollUp => (-3, 0),
+                    MouseEventKind::ScrollDown => (3, 0),
+                    MouseEventKind::ScrollLeft => (0, -6),
+                    MouseEventKind::ScrollRight => (0, 6),
+                    _ => return Ok(()),
+                };
+                // The wheel scrolls the pane under the pointer and leaves
+                // the cursor where it is
+                let Some(pane) = self.panes.get_mut(&hovered.unwrap_or(self.focused)) else {
+                    return Ok(());
+                };
+                if let Some(buffer) = self.buffers.iter().find(|b| b.id == pane.buffer) {
+                    pane.viewport.scroll(&buffer.editor, rows);
+                    pane.viewport.scroll_horizontal(cells);
                 }
             }
+            Mode::Grep => match kind {
+                MouseEventKind::ScrollUp => self.grep.move_selection(-3),
+                MouseEventKind::ScrollDown => self.grep.move_selection(3),
+                MouseEventKind::Down(_)
+                    if row > self.list_area.y
+                        && row < self.list_area.y + self.list_area.height.saturating_sub(1) =>
+                {
+                    let list_height = self.list_area.height.saturating_sub(2) as usize;
+                    let scroll_offset = (self.grep.selected + 1).saturating_sub(list_height);
+                    let clicked = scroll_offset + (row - self.list_area.y - 1) as usize;
+                    if clicked < self.grep.results.len() {
+                        self.grep.selected = clicked;
+                        self.open_grep_match();
+                    }
+                }
+                _ => {}
+            },
+            Mode::Merge => match kind {
+                MouseEventKind::ScrollUp => self.handle_merge_key(KeyCode::Up)?,
+                MouseEventKind::ScrollDown => self.handle_merge_key(KeyCode::Down)?,
+                _ => {}
+            },
         }
         Ok(())
     }
@@ -381,35 +643,16 @@ impl App {
             return self.handle_create_key(code, modifiers);
         }
 
-        if modifiers.contains(KeyModifiers::CONTROL) {
-            if let KeyCode::Char('n') = code {
-                self.start_create_mode();
-                return Ok(());
-            }
+        if let Some(action) = self.config.keys.get(Mode::Search, code, modifiers) {
+            return self.run_action(action);
         }
 
         match code {
-            // Toggle hidden files with Tab key
-            KeyCode::Tab => {
-                self.search.toggle_hidden()?;
-                self.search.update_query(&self.search_input);
-                self.selected_index = 0;
-                self.status_message = Some(format!(
-                    "Hidden files: {}",
-                    if self.search.show_hidden { "SHOWN" } else { "HIDDEN" }
-                ));
-                return Ok(());
-            }
-            KeyCode::Esc => {
-                self.should_quit = true;
-            }
             KeyCode::Enter => {
                 self.handle_enter()?;
             }
             KeyCode::Up => {
-                if self.selected_index > 0 {
-                    self.selected_index -= 1;
-                }
+                self.selected_index = self.selected_index.saturating_sub(1);
             }
             KeyCode::Down => {
                 let max = self.search.match_count().saturating_sub(1);
@@ -432,15 +675,248 @@ impl App {
         Ok(())
     }
 
+    fn handle_grep_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> Result<()> {
+        self.status_message = None;
+        if self.grep.preview.is_some() {
+            self.handle_preview_key(code);
+            return Ok(());
+        }
+        if let Some(action) = self.config.keys.get(Mode::Grep, code, modifiers) {
+            return self.run_action(action);
+        }
+        // Unbound control chords would otherwise type into the query
+        if modifiers.contains(KeyModifiers::CONTROL) {
+            return Ok(());
+        }
+
+        match code {
+            KeyCode::Enter => self.open_grep_match(),
+            KeyCode::Up => self.grep.move_selection(-1),
+            KeyCode::Down => self.grep.move_selection(1),
+            KeyCode::PageUp => self.grep.move_selection(-10),
+            KeyCode::PageDown => self.grep.move_selection(10),
+            KeyCode::Tab if self.grep.replacement.is_some() => {
+                self.grep.replace_focus = !self.grep.replace_focus;
+            }
+            KeyCode::Char(c) => match self.grep.replacement {
+                Some(ref mut replacement) if self.grep.replace_focus => replacement.push(c),
+                _ => {
+                    self.grep.query.push(c);
+                    self.start_grep();
+                }
+            },
+            KeyCode::Backspace => match self.grep.replacement {
+                Some(ref mut replacement) if self.grep.replace_focus => {
+                    replacement.pop();
+                }
+                _ => {
+                    self.grep.query.pop();
+                    self.start_grep();
+                }
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Rerun the content search, reading open buffers rather than the disk.
+    fn start_grep(&mut self) {
+        let open = self
+            .buffers
+            .iter()
+            .filter_map(|b| {
+                let relative = b.editor.path.strip_prefix(&self.search.root).ok()?;
+                Some((relative.to_path_buf(), b.editor.text().clone()))
+            })
+            .collect();
+        self.grep.start(&self.search.root, self.search.show_hidden, open);
+    }
+
+    /// Current text of `path`: the open buffer if there is one, else the
+    /// file on disk.
+    fn current_lines(&self, path: &Path) -> Result<Vec<String>> {
+        match self.buffers.iter().find(|b| b.editor.path == path) {
+            Some(buffer) => Ok(buffer.editor.lines()),
+            None => read_disk_lines(path),
+        }
+    }
+
+    fn preview_replace(&mut self) {
+        let Some(relative) = self.grep.selected_match().map(|m| m.path.clone()) else {
+            return;
+        };
+        let path = self.search.root.join(&relative);
+        let before = match self.current_lines(&path) {
+            Ok(lines) => lines,
+            Err(e) => {
+                self.status_message = Some(format!("Preview failed: {}", e));
+                return;
+            }
+        };
+        let files = self.grep.included_by_file();
+        let hits = files.get(relative.as_path()).cloned().unwrap_or_default();
+        let Some((after, _, _)) = self.grep.apply_to(&before, &hits) else {
+            return;
+        };
+        let diff = swap::diff(&before, &after);
+        self.grep.preview = Some(Preview {
+            path: relative,
+            diff,
+            scroll: 0,
+        });
+    }
+
+    fn handle_preview_key(&mut self, code: KeyCode) {
+        let Some(ref mut preview) = self.grep.preview else {
+            return;
+        };
+        match code {
+            KeyCode::Up => preview.scroll = preview.scroll.saturating_sub(1),
+            KeyCode::Down => {
+                preview.scroll = (preview.scroll + 1).min(preview.diff.len().saturating_sub(1));
+            }
+            KeyCode::PageUp => preview.scroll = preview.scroll.saturating_sub(10),
+            KeyCode::PageDown => {
+                preview.scroll = (preview.scroll + 10).min(preview.diff.len().saturating_sub(1));
+            }
+            _ => self.grep.preview = None,
+        }
+    }
+
+    /// Replace every included hit. Open buffers are edited in place (one
+    /// undo step each); other files are rewritten on disk.
+    fn apply_replace(&mut self) {
+        if !self.grep.can_replace() {
+            self.status_message = Some("Nothing to replace".to_string());
+            return;
+        }
+        let mut undo = ReplaceUndo::default();
+        let (mut applied, mut stale, mut files) = (0, 0, 0);
+        let mut errors = Vec::new();
+
+        for (relative, hits) in self.grep.included_by_file() {
+            let path = self.search.root.join(relative);
+            if let Some(buffer) = self.buffers.iter_mut().find(|b| b.editor.path == path) {
+                let Some((after, n, skipped)) = self.grep.apply_to(&buffer.editor.lines(), &hits)
+                else {
+                    continue;
+                };
+                stale += skipped;
+                if n > 0 {
+                    buffer.editor.replace_contents(after);
+                    undo.buffers.push((buffer.id, buffer.editor.revision()));
+                    applied += n;
+                    files += 1;
+                }
+                continue;
+            }
+
+            match replace_on_disk(&self.grep, &path, &hits, self.backup_mode) {
+                Ok((before, written, n, skipped)) => {
+                    stale += skipped;
+                    if n > 0 {
+                        undo.files.push((path, before, written));
+                        applied += n;
+                        files += 1;
+                    }
+                }
+                Err(e) => errors.push(format!("{}: {}", relative.display(), e)),
+            }
+        }
+
+        let mut message = format!("Replaced {} in {} files", applied, files);
+        if stale > 0 {
+            message.push_str(&format!(", {} changed since the search and skipped", stale));
+        }
+        if let Some(error) = errors.first() {
+            message.push_str(&format!(", {} failed ({})", errors.len(), error));
+        }
+        if applied > 0 {
+            if let Some(key) = self.config.keys.key_for(Mode::Grep, Action::UndoReplace) {
+                message.push_str(&format!(" | {}: undo", key));
+            }
+            self.last_replace = Some(undo);
+        }
+        self.start_grep();
+        self.status_message = Some(message);
+    }
+
+    /// Revert the last replace everywhere it is still in place.
+    fn undo_replace(&mut self) {
+        let Some(undo) = self.last_replace.take() else {
+            self.status_message = Some("Nothing to undo".to_string());
+            return;
+        };
+        let mut skipped = 0;
+        for (path, before, written) in undo.files {
+            let unchanged = fs::read(&path).is_ok_and(|now| now == written);
+            if !unchanged || save::write_file(&path, &before, self.backup_mode).is_err() {
+                skipped += 1;
+            }
+        }
+        for (id, revision) in undo.buffers {
+            match self.buffers.iter_mut().find(|b| b.id == id) {
+                Some(buffer) if buffer.editor.revision() == revision => {
+                    buffer.editor.undo();
+                }
+                _ => skipped += 1,
+            }
+        }
+        self.status_message = Some(if skipped > 0 {
+            format!("Replace undone; {} files changed since and left alone", skipped)
+        } else {
+            "Replace undone".to_string()
+        });
+        self.start_grep();
+    }
+
+    /// Open the selected content match with the cursor on it.
+    /// Open the selected hit. The results are a snapshot, so its file may
+    /// have gone since; that is reported and the results stay up.
+    fn open_grep_match(&mut self) {
+        let Some(m) = self.grep.selected_match() else {
+            return;
+        };
+        let path = self.search.root.join(&m.path);
+        let cursor = (m.line, m.column);
+        if let Err(e) = self.open_file(path) {
+            self.status_message = Some(format!("Open failed: {}", e));
+            return;
+        }
+        if let Some(buffer) = self.active_buffer_mut() {
+            buffer.editor.set_cursor(cursor);
+        }
+    }
+
     fn start_create_mode(&mut self) {
         self.create_active = true;
+        self.create_kind = CreateKind::New;
         self.create_input.clear();
         self.create_base = self.current_base_dir();
     }
 
+    /// Prompt for a new name for the active buffer, starting from its
+    /// current one.
+    fn start_save_as(&mut self) {
+        let Some(editor) = self.active_editor() else {
+            return;
+        };
+        let relative = editor.path.strip_prefix(&self.search.root).ok();
+        let base = relative.and_then(Path::parent).map(Path::to_path_buf).unwrap_or_default();
+        self.create_input = editor.filename();
+        self.create_base = base;
+        self.create_kind = CreateKind::SaveAs;
+        self.create_active = true;
+        self.mode = Mode::Search;
+    }
+
     fn stop_create_mode(&mut self) {
         self.create_active = false;
         self.create_input.clear();
+        // Save-as returns to the buffer it was started from
+        if self.create_kind == CreateKind::SaveAs && self.active_index().is_some() {
+            self.mode = Mode::Edit;
+        }
     }
 
     fn handle_create_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> Result<()> {
@@ -449,26 +925,30 @@ impl App {
                 self.stop_create_mode();
             }
             KeyCode::Enter => {
-                self.apply_create_input();
+                match self.create_kind {
+                    CreateKind::New => self.apply_create_input(),
+                    CreateKind::SaveAs => self.apply_save_as(),
+                }
                 self.stop_create_mode();
             }
             KeyCode::Backspace => {
                 self.create_input.pop();
             }
-            KeyCode::Char(c) => {
-                if !modifiers.contains(KeyModifiers::CONTROL) {
-                    self.create_input.push(c);
-                }
+            KeyCode::Char(c) if !modifiers.contains(KeyModifiers::CONTROL) => {
+                self.create_input.push(c);
             }
             _ => {}
         }
         Ok(())
     }
 
-    fn apply_create_input(&mut self) {
+    /// The typed path relative to the root, and whether it names a
+    /// folder. `None` if nothing was typed or the path is rejected, with
+    /// the reason in the status bar.
+    fn create_target(&mut self) -> Option<(PathBuf, bool)> {
         let raw = self.create_input.trim();
         if raw.is_empty() {
-            return;
+            return None;
         }
 
         let is_dir = raw.ends_with('/') || raw.ends_with(std::path::MAIN_SEPARATOR);
@@ -478,13 +958,13 @@ impl App {
 
         if trimmed.is_empty() {
             self.status_message = Some("Invalid path".to_string());
-            return;
+            return None;
         }
 
         let input_path = PathBuf::from(trimmed);
         if input_path.is_absolute() {
             self.status_message = Some("Absolute paths are not allowed".to_string());
-            return;
+            return None;
         }
 
         let base = self.create_base.clone();
@@ -494,14 +974,19 @@ impl App {
             base.join(input_path)
         };
 
-        let normalized = match normalize_relative(&combined) {
-            Some(path) => path,
+        match normalize_relative(&combined) {
+            Some(path) => Some((path, is_dir)),
             None => {
                 self.status_message = Some("Path escapes root".to_string());
-                return;
+                None
             }
-        };
+        }
+    }
 
+    fn apply_create_input(&mut self) {
+        let Some((normalized, is_dir)) = self.create_target() else {
+            return;
+        };
         let target = self.search.root.join(&normalized);
         let existed = target.exists();
 
@@ -518,7 +1003,7 @@ impl App {
                 Ok(())
             } else {
                 fs::OpenOptions::new()
-                    .create(true)
+                    .create_new(true)
                     .write(true)
                     .open(&target)
                     .map(|_| ())
@@ -541,10 +1026,49 @@ impl App {
         });
     }
 
+    /// Write the active buffer to the typed path and keep editing it
+    /// there. Existing files are not overwritten.
+    fn apply_save_as(&mut self) {
+        let Some((relative, is_dir)) = self.create_target() else {
+            return;
+        };
+        let Some(active) = self.active_index() else {
+            return;
+        };
+        let target = self.search.root.join(&relative);
+        if is_dir {
+            self.status_message = Some("Save as needs a file name".to_string());
+            return;
+        }
+        if target.exists() {
+            self.status_message = Some(format!("{} already exists", relative.display()));
+            return;
+        }
+        if let Some(parent) = target.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                self.status_message = Some(format!("Save failed: {}", e));
+                return;
+            }
+        }
+
+        let buffer = &mut self.buffers[active];
+        if let Err(e) = buffer.editor.save_as(target, self.backup_mode) {
+            self.status_message = Some(format!("Save failed: {}", e));
+            return;
+        }
+        buffer.file_changed_externally = false;
+        buffer.merge = None;
+        self.status_message = Some(match buffer.rewatch() {
+            Ok(()) => format!("Saved as {}", relative.display()),
+            Err(e) => format!("Saved as {}; watcher failed: {}", relative.display(), e),
+        });
+        self.refresh_search();
+    }
+
     fn current_base_dir(&self) -> PathBuf {
         if self.search.search_active {
-            if let Some((path, _)) = self.search.match_path_at(self.selected_index) {
-                return path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+            if let Some(found) = self.search.match_path_at(self.selected_index) {
+                return found.path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
             }
             return PathBuf::new();
         }
@@ -561,48 +1085,767 @@ impl App {
     }
 
     fn handle_edit_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> Result<()> {
-        if let Some(ref mut editor) = self.editor {
-            // Handle Ctrl+R to reload file
-            if modifiers.contains(KeyModifiers::CONTROL) {
-                if let KeyCode::Char('r') = code {
-                    editor.reload()?;
-                    self.file_changed_externally = false;
-                    self.status_message = Some("File reloaded".to_string());
+        // Status messages last until the next key press
+        self.status_message = None;
+
+        if self.find.is_some() {
+            self.handle_find_key(code, modifiers);
+            return Ok(());
+        }
+        if let Some(action) = self.config.keys.get(Mode::Edit, code, modifiers) {
+            return self.run_action(action);
+        }
+        match self.active_buffer_mut() {
+            Some(buffer) => buffer.editor.handle_input(code, modifiers),
+            None => self.mode = Mode::Search,
+        }
+        Ok(())
+    }
+
+    /// Run a bound action. Actions that need an open buffer, or the
+    /// replace mode of the content search, do nothing without one.
+    fn run_action(&mut self, action: Action) -> Result<()> {
+        match action {
+            Action::Quit => self.request_quit(),
+            Action::Back => match self.mode {
+                Mode::Edit => self.leave_editor(),
+                _ => self.mode = Mode::Search,
+            },
+            Action::ToggleHidden => {
+                self.search.toggle_hidden()?;
+                self.search.update_query(&self.search_input);
+                self.selected_index = 0;
+                self.status_message = Some(format!(
+                    "Hidden files: {}",
+                    if self.search.show_hidden { "SHOWN" } else { "HIDDEN" }
+                ));
+            }
+            Action::NewFile => {
+                self.mode = Mode::Search;
+                self.start_create_mode();
+            }
+            Action::BufferPicker => self.open_buffer_picker(),
+            Action::ThemePicker => self.open_theme_picker(),
+            Action::SyntaxPicker => self.open_syntax_picker(),
+            Action::Grep => self.mode = Mode::Grep,
+            Action::Find => {
+                if let Some(editor) = self.active_editor() {
+                    self.find = Some(FindBar::new(editor.cursor_position()));
+                }
+            }
+            Action::Undo | Action::Redo => {
+                if let Some(buffer) = self.active_buffer_mut() {
+                    let done = if action == Action::Undo {
+                        buffer.editor.undo()
+                    } else {
+                        buffer.editor.redo()
+                    };
+                    if !done {
+                        let what = if action == Action::Undo { "undo" } else { "redo" };
+                        self.status_message = Some(format!("Nothing to {}", what));
+                    }
+                }
+            }
+            Action::DeleteToLineEnd => {
+                if let Some(buffer) = self.active_buffer_mut() {
+                    buffer.editor.delete_to_line_end();
+                }
+            }
+            Action::Save => self.save_active(),
+            Action::SaveAs => self.start_save_as(),
+            Action::NextBuffer => self.cycle_buffer(1),
+            Action::PrevBuffer => self.cycle_buffer(-1),
+            Action::CloseBuffer => self.close_active_buffer(),
+            Action::SplitRight => self.split_pane(Direction::Horizontal),
+            Action::SplitDown => self.split_pane(Direction::Vertical),
+            Action::ClosePane => self.close_pane(),
+            Action::ShrinkPane => {
+                self.layout.resize(self.focused, -0.05);
+            }
+            Action::GrowPane => {
+                self.layout.resize(self.focused, 0.05);
+            }
+            Action::FocusLeft => self.focus_side(Side::Left),
+            Action::FocusRight => self.focus_side(Side::Right),
+            Action::FocusUp => self.focus_side(Side::Up),
+            Action::FocusDown => self.focus_side(Side::Down),
+            // Undoable, like any other edit
+            Action::Revert => {
+                if let Some(buffer) = self.active_buffer_mut() {
+                    // A file gone or unreadable leaves the buffer as it is
+                    self.status_message = Some(match buffer.editor.reload() {
+                        Ok(()) => {
+                            buffer.file_changed_externally = false;
+                            buffer.merge = None;
+                            "Reverted to the file on disk".to_string()
+                        }
+                        Err(e) => format!("Revert failed: {}", e),
+                    });
+                }
+            }
+            // Reopens the merge view for an unresolved external change
+            Action::Merge => {
+                let Some(active) = self.active_index() else {
                     return Ok(());
+                };
+                let buffer = &self.buffers[active];
+                if !buffer.file_changed_externally {
+                    self.status_message = Some("No external change to merge".to_string());
+                } else if buffer.merge.is_some() {
+                    self.mode = Mode::Merge;
+                } else {
+                    match read_disk_lines(&buffer.editor.path) {
+                        Ok(theirs) => self.start_merge(active, theirs),
+                        Err(e) => self.status_message = Some(format!("Merge failed: {}", e)),
+                    }
                 }
             }
-
-            if code == KeyCode::Esc {
-                if editor.is_modified() {
-                    editor.save()?;
-                    self.file_changed_externally = false;
+            Action::CycleWrap => {
+                if let Some(buffer) = self.active_buffer_mut() {
+                    let editor = &mut buffer.editor;
+                    editor.wrap = editor.wrap.next();
+                    self.status_message = Some(format!("Wrap: {}", editor.wrap.label()));
                 }
-                self.clear_watcher();
-                self.mode = Mode::Search;
-                self.editor = None;
-                self.status_message = None;
-            } else {
-                editor.handle_input(code, modifiers);
             }
+            Action::ToggleLineEnding | Action::ToggleFinalNewline | Action::CycleEncoding => {
+                if let Some(buffer) = self.active_buffer_mut() {
+                    let editor = &mut buffer.editor;
+                    match action {
+                        Action::ToggleLineEnding => editor.toggle_line_ending(),
+                        Action::ToggleFinalNewline => editor.toggle_final_newline(),
+                        _ => editor.cycle_encoding(),
+                    }
+                    self.status_message = Some(format!("Format: {}", editor.format.describe()));
+                }
+            }
+            Action::ToggleReplace => {
+                if self.grep.replacement.take().is_none() {
+                    self.grep.replacement = Some(String::new());
+                }
+                self.grep.replace_focus = self.grep.replacement.is_some();
+            }
+            Action::ToggleHit if self.grep.replacement.is_some() => {
+                self.grep.toggle_selected();
+                self.grep.move_selection(1);
+            }
+            Action::PreviewReplace if self.grep.replacement.is_some() => self.preview_replace(),
+            Action::ReplaceAll if self.grep.replacement.is_some() => self.apply_replace(),
+            Action::ToggleHit | Action::PreviewReplace | Action::ReplaceAll => {}
+            Action::UndoReplace => self.undo_replace(),
+            // Switches between literal and regex patterns
+            Action::ToggleRegex => {
+                self.grep.regex = !self.grep.regex;
+                self.start_grep();
+            }
+            Action::ToggleScores => self.show_scores = !self.show_scores,
+            Action::ReloadConfig => self.reload_config(),
+            Action::CommandPalette => self.open_command_palette(),
         }
         Ok(())
     }
 
+    fn save_active(&mut self) {
+        let backup = self.backup_mode;
+        let Some(buffer) = self.active_buffer_mut() else {
+            return;
+        };
+        self.status_message = Some(match buffer.editor.save(backup) {
+            Ok(()) => {
+                buffer.file_changed_externally = false;
+                buffer.merge = None;
+                format!("Saved {}", buffer.editor.filename())
+            }
+            Err(e) => format!("Save failed: {}", e),
+        });
+    }
+
+    /// Save every modified buffer, stopping at the first failure, which
+    /// is shown. True if all were saved.
+    fn save_all(&mut self) -> bool {
+        for buffer in self.buffers.iter_mut().filter(|b| b.editor.is_modified()) {
+            if let Err(e) = buffer.editor.save(self.backup_mode) {
+                self.status_message = Some(format!("Save of {} failed: {}", buffer.editor.filename(), e));
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Save the modified buffers the autosave policy says are due: all of
+    /// them when the terminal has lost focus, else those whose edits have
+    /// paused for the idle delay. Buffers with an unmerged external change
+    /// are skipped so it isn't overwritten unseen. True if any was saved.
+    fn autosave(&mut self, focus_lost: bool) -> bool {
+        let now = Instant::now();
+        let mut saved = false;
+        for buffer in &mut self.buffers {
+            let revision = buffer.editor.revision();
+            if buffer.last_edit.0 != revision {
+                buffer.last_edit = (revision, now);
+            }
+            let due = match self.config.autosave {
+                Autosave::FocusLost => focus_lost,
+                Autosave::Idle => now - buffer.last_edit.1 >= self.config.autosave_idle,
+                Autosave::Never | Autosave::OnLeave => false,
+            };
+            if !due || !buffer.editor.is_modified() || buffer.file_changed_externally {
+                continue;
+            }
+            match buffer.editor.save(self.backup_mode) {
+                Ok(()) => saved = true,
+                Err(e) => {
+                    // Try again after another idle delay, not every tick
+                    buffer.last_edit.1 = now;
+                    self.status_message = Some(format!("Autosave of {} failed: {}", buffer.editor.filename(), e));
+                }
+            }
+        }
+        saved
+    }
+
+    /// Quit, or first ask what to do with unsaved changes if there are
+    /// any.
+    fn request_quit(&mut self) {
+        if self.buffers.iter().any(|b| b.editor.is_modified()) {
+            self.quit_prompt = true;
+        } else {
+            self.should_quit = true;
+        }
+    }
+
+    fn handle_quit_key(&mut self, code: KeyCode) {
+        match code {
+            // Save all; a failure cancels the quit
+            KeyCode::Char('s') => {
+                self.quit_prompt = false;
+                self.should_quit = self.save_all();
+            }
+            // Discard all, swap files included so nothing is offered for
+            // recovery next time
+            KeyCode::Char('d') => {
+                for buffer in &mut self.buffers {
+                    buffer.editor.remove_swap();
+                }
+                self.should_quit = true;
+            }
+            KeyCode::Esc | KeyCode::Char('c') => self.quit_prompt = false,
+            _ => {}
+        }
+    }
+
+    /// Back to the file list; the buffer stays open. With autosave on
+    /// leave, a modified buffer is saved first, and a failed save keeps
+    /// the editor open so the edits aren't lost.
+    fn leave_editor(&mut self) {
+        let autosave = self.config.autosave == Autosave::OnLeave;
+        let backup = self.backup_mode;
+        if let Some(buffer) = self.active_buffer_mut().filter(|b| autosave && b.editor.is_modified()) {
+            if let Err(e) = buffer.editor.save(backup) {
+                self.status_message = Some(format!("Save failed: {}", e));
+                return;
+            }
+            buffer.file_changed_externally = false;
+            buffer.merge = None;
+        }
+        self.mode = Mode::Search;
+    }
+
+    fn focus_side(&mut self, side: Side) {
+        if let Some(pane) = layout::neighbour(&self.pane_areas, self.focused, side) {
+            self.focus_pane(pane);
+        }
+    }
+
+    fn handle_find_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        let active = self.active_index();
+        let (Some(find), Some(active)) = (self.find.as_mut(), active) else {
+            self.find = None;
+            return;
+        };
+        let editor = &mut self.buffers[active].editor;
+        find.refresh(editor);
+
+        if modifiers.contains(KeyModifiers::ALT) {
+            match code {
+                KeyCode::Char('r') => find.regex = !find.regex,
+                KeyCode::Char('c') => find.case_sensitive = !find.case_sensitive,
+                KeyCode::Char('w') => find.whole_word = !find.whole_word,
+                _ => return,
+            }
+            find.refresh(editor);
+            find.jump_from_origin(editor);
+            return;
+        }
+        if modifiers.contains(KeyModifiers::CONTROL) {
+            match code {
+                KeyCode::Char('r') if find.show_replace => {
+                    find.replace_current(editor);
+                }
+                KeyCode::Char('a') if find.show_replace => {
+                    let count = find.replace_all(editor);
+                    self.status_message = Some(format!("Replaced {} matches", count));
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match code {
+            KeyCode::Esc => self.find = None,
+            KeyCode::Enter | KeyCode::Down | KeyCode::F(3) => {
+                let forward = !(code == KeyCode::F(3) && modifiers.contains(KeyModifiers::SHIFT));
+                find.jump(editor, forward, false);
+            }
+            KeyCode::Up => {
+                find.jump(editor, false, false);
+            }
+            KeyCode::Tab | KeyCode::BackTab => {
+                find.show_replace = true;
+                find.field = match find.field {
+                    FindField::Query => FindField::Replace,
+                    FindField::Replace => FindField::Query,
+                };
+            }
+            KeyCode::Char(c) => match find.field {
+                FindField::Query => {
+                    find.query.push(c);
+                    find.refresh(editor);
+                    find.jump_from_origin(editor);
+                }
+                FindField::Replace => find.replacement.push(c),
+            },
+            KeyCode::Backspace => match find.field {
+                FindField::Query => {
+                    find.query.pop();
+                    find.refresh(editor);
+                    find.jump_from_origin(editor);
+                }
+                FindField::Replace => {
+                    find.replacement.pop();
+                }
+            },
+            _ => {}
+        }
+    }
+
     fn open_file(&mut self, path: PathBuf) -> Result<()> {
-        self.editor = Some(Editor::open(path.clone())?);
+        if let Some(idx) = self.buffers.iter().position(|b| b.editor.path == path) {
+            self.switch_buffer(idx);
+            return Ok(());
+        }
+
+        let history = self.undo_histories.remove(&path);
+        let id = self.next_buffer_id;
+        let theme = match self.themes.get(&self.theme) {
+            Some(theme) => theme.clone(),
+            None => bail!("Theme {} is not loaded", self.theme),
+        };
+        let (mut buffer, watch_error) =
+            Buffer::open(id, path.clone(), history, self.syntax_set.clone(), theme)?;
+        buffer.editor.tab_width = self.tab_width;
+        buffer.editor.wrap = self.config.wrap;
+        self.next_buffer_id += 1;
+        self.buffers.push(buffer);
+        self.switch_buffer(self.buffers.len() - 1);
+        self.status_message = watch_error.map(|e| format!("Watcher failed: {}", e));
+
+        let editor = &self.buffers[self.buffers.len() - 1].editor;
+        if editor.format.mixed_endings {
+            let key = self.config.keys.key_for(Mode::Edit, Action::ToggleLineEnding);
+            self.status_message = Some(format!(
+                "Mixed line endings: saving is held until {} converts them all to {}",
+                key.as_deref().unwrap_or("toggle_line_ending"),
+                editor.format.line_ending.label()
+            ));
+        }
+        if let Some(swap) = swap::find_recoverable(&path, editor.text()) {
+            let diff = swap::diff(&editor.lines(), &swap.lines);
+            self.recovery = Some(Recovery {
+                swap,
+                diff,
+                scroll: 0,
+            });
+        }
+        Ok(())
+    }
+
+    /// Show the buffer at `idx` in the focused pane.
+    fn switch_buffer(&mut self, idx: usize) {
+        let Some(buffer) = self.buffers.get(idx) else {
+            return;
+        };
+        // Switching to the buffer already shown keeps the scroll position
+        let viewport = match self.panes.get(&self.focused) {
+            Some(pane) if pane.buffer == buffer.id => pane.viewport,
+            _ => Viewport::default(),
+        };
+        let pane = Pane {
+            buffer: buffer.id,
+            cursor: buffer.editor.cursor_position(),
+            viewport,
+        };
+        self.panes.insert(self.focused, pane);
         self.mode = Mode::Edit;
         self.status_message = None;
-        self.file_changed_externally = false;
+    }
 
-        // Setup file watcher
-        if let Err(e) = self.setup_watcher(&path) {
-            self.status_message = Some(format!("Watcher failed: {}", e));
-        } else {
-            self.status_message = Some("File watcher active".to_string());
+    fn cycle_buffer(&mut self, delta: isize) {
+        let len = self.buffers.len();
+        let Some(active) = self.active_index().filter(|_| len >= 2) else {
+            return;
+        };
+        let next = (active as isize + delta).rem_euclid(len as isize) as usize;
+        self.switch_buffer(next);
+    }
+
+    /// Move input to `pane`. The editor is shared by every pane showing
+    /// the buffer, so the cursor is swapped out and back in on focus change.
+    fn focus_pane(&mut self, pane: PaneId) {
+        if pane == self.focused || !self.panes.contains_key(&pane) {
+            return;
+        }
+        if let Some(cursor) = self.active_buffer().map(|b| b.editor.cursor_position()) {
+            if let Some(current) = self.panes.get_mut(&self.focused) {
+                current.cursor = cursor;
+            }
+        }
+        self.focused = pane;
+        self.restore_pane_cursor();
+    }
+
+    fn restore_pane_cursor(&mut self) {
+        let Some(cursor) = self.panes.get(&self.focused).map(|p| p.cursor) else {
+            return;
+        };
+        if let Some(buffer) = self.active_buffer_mut() {
+            buffer.editor.set_cursor(cursor);
+        }
+    }
+
+    /// Split the focused pane, showing the same buffer in the new half.
+    fn split_pane(&mut self, direction: Direction) {
+        let Some(buffer) = self.active_buffer() else {
+            return;
+        };
+        let pane = Pane {
+            buffer: buffer.id,
+            cursor: buffer.editor.cursor_position(),
+            viewport: self.panes.get(&self.focused).map(|p| p.viewport).unwrap_or_default(),
+        };
+        let id = self.next_pane_id;
+        self.next_pane_id += 1;
+        self.layout.split(self.focused, id, direction);
+        self.panes.insert(self.focused, pane.clone());
+        self.panes.insert(id, pane);
+        self.focused = id;
+    }
+
+    fn close_pane(&mut self) {
+        if self.panes.len() < 2 {
+            self.status_message = Some("Cannot close the last pane".to_string());
+            return;
+        }
+        let Some(sibling) = self.layout.remove(self.focused) else {
+            return;
+        };
+        self.panes.remove(&self.focused);
+        self.focused = sibling;
+        self.restore_pane_cursor();
+    }
+
+    /// Close the active buffer, saving it first if modified under the
+    /// on-leave autosave policy; under the others a modified buffer is not
+    /// closed. Its undo history is kept for when the file is reopened.
+    fn close_active_buffer(&mut self) {
+        let Some(active) = self.active_index() else {
+            return;
+        };
+        let buffer = &mut self.buffers[active];
+        if buffer.editor.is_modified() {
+            // Saving on close belongs to the on-leave policy; the others
+            // keep the buffer open rather than save or drop its changes
+            if self.config.autosave != Autosave::OnLeave {
+                self.status_message = Some(format!("{} has unsaved changes", buffer.editor.filename()));
+                return;
+            }
+            if let Err(e) = buffer.editor.save(self.backup_mode) {
+                self.status_message = Some(format!("Save failed: {}", e));
+                return;
+            }
+        }
+
+        let mut buffer = self.buffers.remove(active);
+        buffer.editor.remove_swap();
+        if let Err(e) = buffer.editor.persist_history() {
+            self.status_message = Some(format!("Undo history not saved: {}", e));
+        }
+        let (path, history) = buffer.editor.into_history();
+        self.undo_histories.insert(path, history);
+
+        if self.buffers.is_empty() {
+            self.panes.clear();
+            self.layout = Node::Leaf(self.focused);
+            self.mode = Mode::Search;
+            return;
+        }
+        // Panes that showed the closed buffer move on to a neighbouring one
+        let replacement = &self.buffers[active.min(self.buffers.len() - 1)];
+        for pane in self.panes.values_mut() {
+            if pane.buffer == buffer.id {
+                pane.buffer = replacement.id;
+                pane.cursor = replacement.editor.cursor_position();
+            }
+        }
+        self.restore_pane_cursor();
+    }
+
+    /// Display name for a buffer: its path relative to the root.
+    pub fn buffer_name(&self, buffer: &Buffer) -> String {
+        let path = &buffer.editor.path;
+        path.strip_prefix(&self.search.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn open_buffer_picker(&mut self) {
+        if self.buffers.is_empty() {
+            self.status_message = Some("No open buffers".to_string());
+            return;
+        }
+        let names = self.buffers.iter().map(|b| self.buffer_name(b)).collect();
+        self.picker = Some(Picker::new(PickerKind::Buffer, " Buffers ", names));
+    }
+
+    fn open_theme_picker(&mut self) {
+        let names = self.themes.names();
+        let current = names.iter().position(|name| *name == self.theme);
+        let mut picker = Picker::new(PickerKind::Theme, " Themes ", names);
+        picker.selected = current.unwrap_or_default();
+        self.picker = Some(picker);
+    }
+
+    fn open_syntax_picker(&mut self) {
+        let Some(editor) = self.active_editor() else {
+            return;
+        };
+        let current = editor.syntax_name().to_string();
+        let mut names: Vec<String> = self
+            .syntax_set
+            .syntaxes()
+            .iter()
+            .filter(|syntax| !syntax.hidden)
+            .map(|syntax| syntax.name.clone())
+            .collect();
+        names.sort_by_key(|name| name.to_lowercase());
+        names.dedup();
+        let selected = names.iter().position(|name| *name == current).map_or(0, |idx| idx + 1);
+        names.insert(0, AUTO_SYNTAX.to_string());
+        let mut picker = Picker::new(PickerKind::Syntax, " Syntax ", names);
+        picker.selected = selected;
+        self.picker = Some(picker);
+    }
+
+    /// List the actions for the current mode with their keys.
+    fn open_command_palette(&mut self) {
+        let actions = Action::ALL
+            .iter()
+            .copied()
+            .filter(|action| action.applies_to(self.mode) && *action != Action::CommandPalette);
+        let (titles, keys) = actions
+            .map(|action| {
+                let key = self.config.keys.key_for(self.mode, action).unwrap_or_default();
+                (action.title().to_string(), key)
+            })
+            .unzip();
+        let mut picker = Picker::new(PickerKind::Command, " Commands ", titles);
+        picker.hints = keys;
+        self.picker = Some(picker);
+    }
+
+    /// Read the config files again and apply them to the open session. A
+    /// broken file leaves the current settings in place.
+    fn reload_config(&mut self) {
+        let config = match config::load(&self.search.root) {
+            Ok(config) => config,
+            Err(e) => {
+                self.status_message = Some(format!("Config: {:#}", e));
+                return;
+            }
+        };
+        let hidden_changed = config.show_hidden != self.search.show_hidden;
+        // Wrap modes picked per buffer with Alt+Z only give way to a new
+        // configured one
+        let wrap_changed = config.wrap != self.config.wrap;
+        self.tab_width = tab_width_from_env().unwrap_or(config.tab_width);
+        self.backup_mode = BackupMode::from_env().unwrap_or(config.backup);
+        for buffer in &mut self.buffers {
+            buffer.editor.tab_width = self.tab_width;
+            if wrap_changed {
+                buffer.editor.wrap = config.wrap;
+            }
+        }
+        self.theme = theme::initial_name(&self.themes, config.theme.as_deref());
+        self.config = config;
+        let theme = self.theme.clone();
+        self.apply_theme(&theme);
+        self.status_message = Some("Config reloaded".to_string());
+
+        if hidden_changed {
+            if let Err(e) = self.search.toggle_hidden() {
+                self.status_message = Some(format!("Refresh failed: {}", e));
+            }
+            self.search.update_query(&self.search_input);
+            self.selected_index = 0;
+        }
+    }
+
+    /// Show theme `name` in every buffer and the UI.
+    fn apply_theme(&mut self, name: &str) {
+        let Some(theme) = self.themes.get(name) else {
+            return;
+        };
+        self.palette = Palette::from_theme(theme);
+        self.config.colors.apply(&mut self.palette);
+        for buffer in &mut self.buffers {
+            buffer.editor.set_theme(theme.clone());
         }
-        Ok(())
     }
 
+    fn handle_picker_key(&mut self, code: KeyCode) {
+        let Some(mut picker) = self.picker.take() else {
+            return;
+        };
+        match code {
+            KeyCode::Esc => {
+                if picker.kind == PickerKind::Theme {
+                    let theme = self.theme.clone();
+                    self.apply_theme(&theme);
+                }
+                return;
+            }
+            KeyCode::Enter => {
+                if let Some(item) = picker.selected_item() {
+                    match picker.kind {
+                        PickerKind::Buffer => self.switch_buffer(item),
+                        PickerKind::Syntax => {
+                            let name = Some(picker.items[item].clone()).filter(|_| item > 0);
+                            if let Some(active) = self.active_index() {
+                                let editor = &mut self.buffers[active].editor;
+                                editor.set_syntax(name);
+                                self.status_message = Some(format!("Syntax: {}", editor.syntax_name()));
+                            }
+                        }
+                        PickerKind::Theme => {
+                            self.theme = picker.items[item].clone();
+                            self.apply_theme(&picker.items[item]);
+                            self.status_message = Some(format!("Theme: {}", self.theme));
+                        }
+                        PickerKind::Command => {
+                            let title = picker.items[item].as_str();
+                            if let Some(&action) = Action::ALL.iter().find(|action| action.title() == title) {
+                                if let Err(e) = self.run_action(action) {
+                                    self.status_message = Some(format!("{}: {}", title, e));
+                                }
+                            }
+                        }
+                    }
+                } else if picker.kind == PickerKind::Theme {
+                    let theme = self.theme.clone();
+                    self.apply_theme(&theme);
+                }
+                return;
+            }
+            KeyCode::Up => picker.move_selection(-1),
+            KeyCode::Down => picker.move_selection(1),
+            KeyCode::Backspace => {
+                picker.query.pop();
+                picker.update(&mut self.search);
+            }
+            KeyCode::Char(c) => {
+                picker.query.push(c);
+                picker.update(&mut self.search);
+            }
+            _ => {}
+        }
+        if picker.kind == PickerKind::Theme {
+            if let Some(item) = picker.selected_item() {
+                self.apply_theme(&picker.items[item]);
+            }
+        }
+        self.picker = Some(picker);
+    }
+
+    fn handle_recovery_key(&mut self, code: KeyCode) {
+        let Some(mut recovery) = self.recovery.take() else {
+            return;
+        };
+        match code {
+            KeyCode::Char('r') => {
+                if let Some(buffer) = self.active_buffer_mut() {
+                    buffer.editor.replace_contents(recovery.swap.lines);
+                    self.status_message = Some("Recovered unsaved edits from swap".to_string());
+                }
+            }
+            KeyCode::Char('d') => {
+                let _ = fs::remove_file(&recovery.swap.swap_path);
+                self.status_message = Some("Swap file deleted".to_string());
+            }
+            KeyCode::Esc => {
+                self.status_message = Some("Swap file kept".to_string());
+            }
+            KeyCode::Up => {
+                recovery.scroll = recovery.scroll.saturating_sub(1);
+                self.recovery = Some(recovery);
+            }
+            KeyCode::Down => {
+                recovery.scroll = (recovery.scroll + 1).min(recovery.diff.len().saturating_sub(1));
+                self.recovery = Some(recovery);
+            }
+            _ => self.recovery = Some(recovery),
+        }
+    }
+
+}
+
+/// First entry of the syntax picker, going back to detection.
+const AUTO_SYNTAX: &str = "Auto-detect";
+
+/// `TEDITOR_TAB_WIDTH` if set to a positive number; it overrides the
+/// config.
+fn tab_width_from_env() -> Option<usize> {
+    std::env::var("TEDITOR_TAB_WIDTH")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|&width| width > 0)
+}
+
+/// Apply `hits` to the file at `path`, keeping its format. Returns the
+/// bytes before and after, and how many hits were applied and skipped.
+fn replace_on_disk(
+    grep: &Grep,
+    path: &Path,
+    hits: &[&GrepMatch],
+    backup: BackupMode,
+) -> Result<(Vec<u8>, Vec<u8>, usize, usize)> {
+    let before = fs::read(path)?;
+    let (lines, format) = file_format::decode(&before)?;
+    if format.mixed_endings {
+        bail!("mixed line endings; open it to pick LF or CRLF");
+    }
+    let Some((after, applied, skipped)) = grep.apply_to(&lines, hits) else {
+        bail!("not in replace mode");
+    };
+    if applied == 0 {
+        return Ok((before, Vec::new(), 0, skipped));
+    }
+    let written = file_format::encode(&after, &format)?;
+    save::write_file(path, &written, backup)?;
+    Ok((before, written, applied, skipped))
+}
+
+fn read_disk_lines(path: &Path) -> Result<Vec<String>> {
+    Ok(file_format::decode(&fs::read(path)?)?.0)
 }
 
 fn normalize_relative(path: &Path) -> Option<PathBuf> {
@@ -0,0 +1,131 @@
+use crate::editor::Editor;
+use crate::merge::Merge;
+use crate::undo::UndoTree;
+use anyhow::Result;
+use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use syntect::highlighting::Theme;
+use syntect::parsing::SyntaxSet;
+
+/// Conflict resolution between the buffer and an external change.
+pub struct MergeState {
+    pub merge: Merge,
+    /// Index into `merge.conflicts()`
+    pub selected: usize,
+}
+
+/// An open file: its editor plus the watcher reporting changes to it.
+pub struct Buffer {
+    /// Stable identifier panes use to refer to this buffer
+    pub id: usize,
+    pub editor: Editor,
+    pub file_changed_externally: bool,
+    pub merge: Option<MergeState>,
+    /// Editor revision as of the last check and when it was first seen,
+    /// for saving once edits have paused
+    pub last_edit: (u64, Instant),
+    /// When the latest watcher event arrived, until the file is reread
+    pending_change: Option<Instant>,
+    _watcher: Option<RecommendedWatcher>,
+    watcher_rx: Option<Receiver<PathBuf>>,
+}
+
+impl Buffer {
+    /// Open `path` and start watching it. A watcher failure is returned
+    /// alongside the buffer rather than failing the open.
+    pub fn open(
+        id: usize,
+        path: PathBuf,
+        history: Option<UndoTree>,
+        syntax_set: Arc<SyntaxSet>,
+        theme: Arc<Theme>,
+    ) -> Result<(Self, Option<anyhow::Error>)> {
+        let editor = Editor::open(path.clone(), history, syntax_set, theme)?;
+        let mut buffer = Self {
+            id,
+            last_edit: (editor.revision(), Instant::now()),
+            editor,
+            file_changed_externally: false,
+            merge: None,
+            pending_change: None,
+            _watcher: None,
+            watcher_rx: None,
+        };
+        let watch_error = match watch_file(&path) {
+            Ok((watcher, rx)) => {
+                buffer._watcher = Some(watcher);
+                buffer.watcher_rx = Some(rx);
+                None
+            }
+            Err(e) => Some(e),
+        };
+        Ok((buffer, watch_error))
+    }
+
+    /// Watch the editor's path again after it changed with save-as.
+    pub fn rewatch(&mut self) -> Result<()> {
+        self._watcher = None;
+        self.watcher_rx = None;
+        self.pending_change = None;
+        let (watcher, rx) = watch_file(&self.editor.path)?;
+        self._watcher = Some(watcher);
+        self.watcher_rx = Some(rx);
+        Ok(())
+    }
+
+    /// Drain pending watcher events. True once the file was touched and
+    /// then left alone for `debounce`, so a burst of writes is read once.
+    pub fn poll_changes(&mut self, debounce: Duration) -> bool {
+        if let Some(ref rx) = self.watcher_rx {
+            // Non-blocking check for file change events
+            while rx.try_recv().is_ok() {
+                self.pending_change = Some(Instant::now());
+            }
+        }
+        match self.pending_change {
+            Some(at) if at.elapsed() >= debounce => {
+                self.pending_change = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn watch_file(path: &Path) -> Result<(RecommendedWatcher, Receiver<PathBuf>)> {
+    let (tx, rx) = mpsc::channel();
+    let path_clone = path.to_path_buf();
+    let file_name = path.file_name().map(|n| n.to_os_string());
+
+    let mut watcher = RecommendedWatcher::new(
+        move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                // Only events that touch the watched file itself
+                if !event.paths.iter().any(|p| p.file_name() == file_name.as_deref()) {
+                    return;
+                }
+                // Check for any data modification events
+                use notify::EventKind;
+                match event.kind {
+                    EventKind::Modify(_) | EventKind::Create(_) => {
+                        let _ = tx.send(path_clone.clone());
+                    }
+                    _ => {}
+                }
+            }
+        },
+        Config::default(),
+    )?;
+
+    // Watch the directory rather than the file: saves that rename a new
+    // file into place (ours included) would orphan a watch on the old inode
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    Ok((watcher, rx))
+}
@@ -0,0 +1,298 @@
+use anyhow::{anyhow, bail, Context, Result};
+use ratatui::style::Color;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::app::Mode;
+use crate::editor::DEFAULT_TAB_WIDTH;
+use crate::keymap::{Action, KeyChord, Keymap};
+use crate::paths;
+use crate::save::BackupMode;
+use crate::theme::Palette;
+use crate::viewport::WrapMode;
+
+/// Name of the per-project config, looked up in the root directory.
+pub const PROJECT_FILE: &str = ".teditor.toml";
+
+/// When modified buffers are written without being asked to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Autosave {
+    /// Only when saved explicitly
+    Never,
+    /// On leaving the editor with Esc and on closing the buffer
+    #[default]
+    OnLeave,
+    /// Once edits have paused for `autosave_idle`
+    Idle,
+    /// When the terminal loses focus
+    FocusLost,
+}
+
+/// Settings merged from the user's `config.toml` and the project's
+/// `.teditor.toml`, the project winning.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Syntax theme; `None` keeps the default
+    pub theme: Option<String>,
+    pub tab_width: usize,
+    /// Wrap mode new buffers start in
+    pub wrap: WrapMode,
+    pub show_hidden: bool,
+    /// How long the file list and open buffers wait after a change on disk
+    /// before rescanning or rereading, so a burst of changes costs one
+    pub watch_debounce: Duration,
+    pub autosave: Autosave,
+    pub autosave_idle: Duration,
+    /// Copy of the previous contents kept on save
+    pub backup: BackupMode,
+    /// UI colours replacing the theme's
+    pub colors: Colors,
+    pub keys: Keymap,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            theme: None,
+            tab_width: DEFAULT_TAB_WIDTH,
+            wrap: WrapMode::default(),
+            show_hidden: true,
+            watch_debounce: Duration::from_millis(300),
+            autosave: Autosave::default(),
+            autosave_idle: Duration::from_secs(5),
+            backup: BackupMode::default(),
+            colors: Colors::default(),
+            keys: Keymap::default(),
+        }
+    }
+}
+
+/// A config file as written. Everything is optional so a file only has
+/// to mention what it changes.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    theme: Option<String>,
+    tab_width: Option<usize>,
+    wrap: Option<WrapMode>,
+    show_hidden: Option<bool>,
+    watch_debounce_ms: Option<u64>,
+    autosave: Option<Autosave>,
+    autosave_idle_secs: Option<u64>,
+    backup: Option<BackupMode>,
+    colors: HashMap<String, String>,
+    /// Mode name to key to action name
+    keys: HashMap<String, HashMap<String, String>>,
+}
+
+impl Config {
+    /// The autosave policy as shown in the status bar.
+    pub fn autosave_label(&self) -> String {
+        match self.autosave {
+            Autosave::Never => "autosave off".to_string(),
+            Autosave::OnLeave => "autosave on leave".to_string(),
+            Autosave::Idle => format!("autosave after {}s idle", self.autosave_idle.as_secs()),
+            Autosave::FocusLost => "autosave on focus loss".to_string(),
+        }
+    }
+}
+
+/// Palette entries set by the config, each as its field name and colour.
+#[derive(Debug, Clone, Default)]
+pub struct Colors(Vec<(String, Color)>);
+
+impl Colors {
+    pub fn apply(&self, palette: &mut Palette) {
+        for (name, color) in &self.0 {
+            if let Some(slot) = palette_slot(palette, name) {
+                *slot = *color;
+            }
+        }
+    }
+}
+
+fn palette_slot<'a>(palette: &'a mut Palette, name: &str) -> Option<&'a mut Color> {
+    let slot = match name {
+        "background" => &mut palette.background,
+        "foreground" => &mut palette.foreground,
+        "selection" => &mut palette.selection,
+        "selection_fg" => &mut palette.selection_fg,
+        "gutter" => &mut palette.gutter,
+        "status_bg" => &mut palette.status_bg,
+        "status_fg" => &mut palette.status_fg,
+        "accent" => &mut palette.accent,
+        "accent_fg" => &mut palette.accent_fg,
+        "match_bg" => &mut palette.match_bg,
+        "current_match_bg" => &mut palette.current_match_bg,
+        "added" => &mut palette.added,
+        "removed" => &mut palette.removed,
+        "warning" => &mut palette.warning,
+        _ => return None,
+    };
+    Some(slot)
+}
+
+/// `config.toml` in the config directory.
+pub fn user_path() -> Option<PathBuf> {
+    paths::config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// Load the user config and then the project config in `root` over it.
+/// Missing files are fine; anything wrong in one that exists is an error
+/// naming the file.
+pub fn load(root: &Path) -> Result<Config> {
+    let mut config = Config::default();
+    let files = user_path().into_iter().chain([root.join(PROJECT_FILE)]);
+    for path in files {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("{}", path.display())),
+        };
+        apply(&mut config, &text).with_context(|| format!("{}", path.display()))?;
+    }
+    Ok(config)
+}
+
+/// toml's error on one line, fit for the status bar.
+fn parse_error(text: &str, error: &toml::de::Error) -> anyhow::Error {
+    let message = match error.message().trim_end() {
+        "" => "invalid TOML",
+        message => message,
+    };
+    let Some(span) = error.span() else {
+        return anyhow!("{}", message);
+    };
+    let before = &text[..span.start.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |at| at + 1) + 1;
+    anyhow!("line {}, column {}: {}", line, column, message)
+}
+
+fn apply(config: &mut Config, text: &str) -> Result<()> {
+    let file: ConfigFile = toml::from_str(text).map_err(|e| parse_error(text, &e))?;
+
+    if let Some(theme) = file.theme {
+        config.theme = Some(theme);
+    }
+    if let Some(tab_width) = file.tab_width {
+        if tab_width == 0 {
+            bail!("tab_width must be at least 1");
+        }
+        config.tab_width = tab_width;
+    }
+    if let Some(wrap) = file.wrap {
+        config.wrap = wrap;
+    }
+    if let Some(show_hidden) = file.show_hidden {
+        config.show_hidden = show_hidden;
+    }
+    if let Some(ms) = file.watch_debounce_ms {
+        config.watch_debounce = Duration::from_millis(ms);
+    }
+    if let Some(autosave) = file.autosave {
+        config.autosave = autosave;
+    }
+    if let Some(secs) = file.autosave_idle_secs {
+        if secs == 0 {
+            bail!("autosave_idle_secs must be at least 1");
+        }
+        config.autosave_idle = Duration::from_secs(secs);
+    }
+    if let Some(backup) = file.backup {
+        config.backup = backup;
+    }
+
+    for (name, value) in file.colors {
+        if palette_slot(&mut Palette::default(), &name).is_none() {
+            bail!("unknown colour `{}` in [colors]", name);
+        }
+        let color = value
+            .parse::<Color>()
+            .map_err(|_| anyhow!("invalid colour `{}` for {} (use a name or #rrggbb)", value, name))?;
+        config.colors.0.push((name, color));
+    }
+
+    for (mode_name, bindings) in file.keys {
+        let mode = match mode_name.as_str() {
+            "search" => Mode::Search,
+            "edit" => Mode::Edit,
+            "grep" => Mode::Grep,
+            _ => bail!("unknown mode `{}` in [keys] (expected search, edit or grep)", mode_name),
+        };
+        for (key, action_name) in bindings {
+            let chord = KeyChord::parse(&key).with_context(|| format!("[keys.{}]", mode_name))?;
+            // "none" frees a key without binding it to anything
+            let action = match action_name.as_str() {
+                "none" => None,
+                name => Some(Action::from_name(name).ok_or_else(|| {
+                    anyhow!("unknown action `{}` for `{}` in [keys.{}]", name, key, mode_name)
+                })?),
+            };
+            config.keys.bind(mode, chord, action);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    fn applied(text: &str) -> Result<Config> {
+        let mut config = Config::default();
+        apply(&mut config, text)?;
+        Ok(config)
+    }
+
+    fn error(text: &str) -> String {
+        format!("{:#}", applied(text).unwrap_err())
+    }
+
+    #[test]
+    fn settings_override_the_defaults() {
+        let config = applied("tab_width = 2\nwrap = \"off\"\nbackup = \"bak\"\nwatch_debounce_ms = 50").unwrap();
+        assert_eq!(config.tab_width, 2);
+        assert_eq!(config.wrap, WrapMode::Off);
+        assert_eq!(config.backup, BackupMode::Bak);
+        assert_eq!(config.watch_debounce, Duration::from_millis(50));
+        assert_eq!(config.autosave, Autosave::OnLeave);
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(error("tab_widht = 2").contains("unknown field `tab_widht`"));
+        assert!(error("[colors]\nbackground = \"red\"\n[colours]").contains("unknown field `colours`"));
+    }
+
+    #[test]
+    fn parse_errors_give_line_and_column() {
+        let message = error("theme = \"base16-ocean.dark\"\ntab_width = \"two\"\n");
+        assert!(message.starts_with("line 2, column 13: "), "{}", message);
+        let message = error("theme = \"x\"\n\nwrap = \"sideways\"");
+        assert!(message.starts_with("line 3, column 8: "), "{}", message);
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        assert_eq!(error("tab_width = 0"), "tab_width must be at least 1");
+        assert!(error("[colors]\nbackground = \"nope\"").contains("invalid colour `nope`"));
+        assert!(error("[colors]\nborder = \"red\"").contains("unknown colour `border`"));
+    }
+
+    #[test]
+    fn keys_bind_and_unbind() {
+        let config = applied("[keys.edit]\n\"ctrl+e\" = \"grep\"\n\"ctrl+s\" = \"none\"").unwrap();
+        let ctrl = KeyModifiers::CONTROL;
+        assert_eq!(config.keys.get(Mode::Edit, KeyCode::Char('e'), ctrl), Some(Action::Grep));
+        assert_eq!(config.keys.get(Mode::Edit, KeyCode::Char('s'), ctrl), None);
+        assert!(error("[keys.edit]\n\"ctrl+e\" = \"fly\"").contains("unknown action `fly`"));
+        assert!(error("[keys.nowhere]\n\"ctrl+e\" = \"grep\"").contains("unknown mode `nowhere`"));
+    }
+}
@@ -1,168 +1,617 @@
 use anyhow::Result;
 use crossterm::event::{KeyCode, KeyModifiers};
 use ratatui::prelude::*;
+use ropey::Rope;
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::fs;
 use std::path::PathBuf;
-use syntect::easy::HighlightLines;
-use syntect::highlighting::{Style as SyntectStyle, Theme, ThemeSet};
-use syntect::parsing::SyntaxSet;
-use syntect::util::LinesWithEndings;
-use tui_textarea::TextArea;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use syntect::highlighting::Theme;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::file_format::{self, FileFormat};
+use crate::highlight::HighlightCache;
+use crate::save::{self, BackupMode, SaveError};
+use crate::swap;
+use crate::syntax;
+use crate::text;
+use crate::undo::{self, Edit, UndoTree};
+use crate::viewport::{LineLayout, WrapMode};
+
+/// Tab stop width unless `TEDITOR_TAB_WIDTH` says otherwise.
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// Revisions back that `splices_since` can account for.
+const SPLICE_LOG: usize = 256;
 
 pub struct Editor {
     pub path: PathBuf,
-    pub textarea: TextArea<'static>,
-    pub syntax_set: SyntaxSet,
-    pub theme: Theme,
-    modified: bool,
-    original_hash: u64,
-    highlighted_lines: Vec<Vec<(Style, String)>>,
-    line_lengths: Vec<usize>,
-    content_hash: u64,
+    text: Rope,
+    /// (row, column in chars)
+    cursor: (usize, usize),
+    /// Display column that up/down movement tries to keep through shorter lines
+    preferred_col: Option<usize>,
+    /// Rows moved by PageUp/PageDown, set from the view when drawn
+    pub page_height: usize,
+    /// Cells between tab stops, for drawing tabs and for the Tab key
+    pub tab_width: usize,
+    /// How panes showing this buffer fit long lines
+    pub wrap: WrapMode,
+    /// Shared by all buffers
+    pub syntax_set: Arc<SyntaxSet>,
+    /// Syntax detected from the file, and one picked by hand that wins
+    /// over it. Names in `syntax_set`.
+    detected_syntax: Option<String>,
+    syntax_override: Option<String>,
+    pub theme: Arc<Theme>,
+    pub format: FileFormat,
+    saved_format: FileFormat,
+    /// History node whose text matches the file on disk, if any
+    saved_node: Option<usize>,
+    highlight: HighlightCache,
+    /// Changes with every edit; see `next_revision`
+    revision: u64,
+    /// Latest revision changes as (revision before, start line, lines
+    /// removed, lines inserted); format changes remove and insert none
+    splices: VecDeque<(u64, usize, usize, usize)>,
+    history: UndoTree,
+    /// Revision last written to the swap file, if one exists.
+    swap_revision: Option<u64>,
+    /// When the buffer was last saved this session
+    saved_at: Option<Instant>,
+    /// Text as last loaded from or saved to disk; the base for three-way merges.
+    base: Rope,
 }
 
 impl Editor {
-    pub fn open(path: PathBuf) -> Result<Self> {
-        let content = fs::read_to_string(&path)?;
-        let original_hash = simple_hash(&content);
+    /// Open `path`, reattaching `history` (or the history persisted on disk)
+    /// when it was recorded against the file's current content. Highlights
+    /// with the shared `syntax_set` and `theme`.
+    pub fn open(
+        path: PathBuf,
+        history: Option<UndoTree>,
+        syntax_set: Arc<SyntaxSet>,
+        theme: Arc<Theme>,
+    ) -> Result<Self> {
+        let (text, format) = file_format::decode_text(&fs::read(&path)?)?;
+        let text = Rope::from_str(&text);
+        let original_hash = hash_chunks(text.chunks());
+        let history = history
+            .filter(|h| h.content_hash() == original_hash)
+            .or_else(|| undo::load(&path, original_hash))
+            .unwrap_or_else(|| UndoTree::new(original_hash));
+
+        let detected_syntax = syntax::detect(&syntax_set, &path, &text).map(|s| s.name.clone());
+
+        Ok(Self {
+            path,
+            highlight: HighlightCache::new(text.len_lines()),
+            base: text.clone(),
+            text,
+            cursor: (0, 0),
+            preferred_col: None,
+            page_height: 20,
+            tab_width: DEFAULT_TAB_WIDTH,
+            wrap: WrapMode::default(),
+            syntax_set,
+            detected_syntax,
+            syntax_override: None,
+            theme,
+            format,
+            saved_format: format,
+            saved_node: Some(history.current()),
+            revision: next_revision(),
+            splices: VecDeque::new(),
+            history,
+            swap_revision: None,
+            saved_at: None,
+        })
+    }
 
-        let lines: Vec<String> = content.lines().map(String::from).collect();
-        let mut textarea = TextArea::new(lines);
+    /// Switch syntax themes. Highlighting starts over, from the lines
+    /// next drawn.
+    pub fn set_theme(&mut self, theme: Arc<Theme>) {
+        self.theme = theme;
+        self.highlight = HighlightCache::new(self.line_count());
+    }
 
-        textarea.set_cursor_line_style(ratatui::style::Style::default());
-        textarea.set_line_number_style(ratatui::style::Style::default().fg(ratatui::style::Color::DarkGray));
+    /// Pick the syntax by hand, or go back to detecting it with `None`.
+    pub fn set_syntax(&mut self, name: Option<String>) {
+        self.syntax_override = name;
+        self.highlight = HighlightCache::new(self.line_count());
+    }
 
-        let syntax_set = SyntaxSet::load_defaults_newlines();
-        let theme_set = ThemeSet::load_defaults();
-        let theme = theme_set.themes["base16-ocean.dark"].clone();
+    /// Detect the syntax again from the path and text, restarting the
+    /// highlighting if it changed.
+    fn detect_syntax(&mut self) {
+        let detected = syntax::detect(&self.syntax_set, &self.path, &self.text).map(|s| s.name.clone());
+        if detected != self.detected_syntax {
+            self.detected_syntax = detected;
+            if self.syntax_override.is_none() {
+                self.highlight = HighlightCache::new(self.line_count());
+            }
+        }
+    }
 
-        let mut editor = Self {
-            path,
-            textarea,
-            syntax_set,
-            theme,
-            modified: false,
-            original_hash,
-            highlighted_lines: Vec::new(),
-            line_lengths: Vec::new(),
-            content_hash: 0,
+    fn syntax(&self) -> Option<&SyntaxReference> {
+        let name = self.syntax_override.as_ref().or(self.detected_syntax.as_ref())?;
+        self.syntax_set.find_syntax_by_name(name)
+    }
+
+    /// Name of the syntax in use, for display.
+    pub fn syntax_name(&self) -> &str {
+        self.syntax().map_or(syntax::PLAIN_TEXT, |syntax| syntax.name.as_str())
+    }
+
+    /// Highlight at least up to line `row`, ready for drawing.
+    pub fn highlight_until(&mut self, row: usize) {
+        // Plain text has nothing to colour; skipping it keeps huge logs
+        // from being run through the parser line by line
+        let name = self.syntax_override.as_ref().or(self.detected_syntax.as_ref());
+        let syntax = name.and_then(|name| self.syntax_set.find_syntax_by_name(name));
+        let Some(syntax) = syntax.filter(|syntax| syntax.name != syntax::PLAIN_TEXT) else {
+            return;
         };
-        editor.update_highlighting();
-        Ok(editor)
+        self.highlight
+            .highlight_until(&self.text, row, &self.syntax_set, syntax, &self.theme);
     }
 
-    fn update_highlighting(&mut self) {
-        let content = self.textarea.lines().join("\n");
-        let new_hash = simple_hash(&content);
+    pub fn text(&self) -> &Rope {
+        &self.text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.text.len_lines()
+    }
+
+    pub fn line(&self, idx: usize) -> Cow<'_, str> {
+        text::line(&self.text, idx)
+    }
+
+    /// Length of line `idx` in chars.
+    pub fn line_len(&self, idx: usize) -> usize {
+        text::line_len(&self.text, idx)
+    }
+
+    /// The whole text as owned lines. Costs a copy of the buffer, so keep
+    /// it to one-off operations like merges.
+    pub fn lines(&self) -> Vec<String> {
+        text::to_lines(&self.text)
+    }
+
+    /// Highlighted spans for line `idx`, or the plain text if it has not
+    /// been highlighted yet.
+    pub fn line_spans(&self, idx: usize) -> Cow<'_, [(Style, String)]> {
+        match self.highlight.spans(idx) {
+            Some(spans) => Cow::Borrowed(spans),
+            None => {
+                let line = self.line(idx);
+                if line.is_empty() {
+                    Cow::Owned(Vec::new())
+                } else {
+                    Cow::Owned(vec![(Style::default(), line.into_owned())])
+                }
+            }
+        }
+    }
 
-        self.modified = new_hash != self.original_hash;
+    /// Identifies the current text; changes with every edit.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
 
-        if new_hash == self.content_hash && !self.highlighted_lines.is_empty() {
+    /// How the lines changed since `revision`, as (start line, lines
+    /// removed, lines inserted) in the order applied. `None` if `revision`
+    /// is too old or from another buffer, when only a full rescan will do.
+    pub fn splices_since(&self, revision: u64) -> Option<impl Iterator<Item = (usize, usize, usize)> + '_> {
+        let first = if revision == self.revision {
+            self.splices.len()
+        } else {
+            self.splices.iter().position(|&(before, ..)| before == revision)?
+        };
+        Some(self.splices.range(first..).map(|&(_, start, removed, inserted)| (start, removed, inserted)))
+    }
+
+    /// Move to a new revision, noting that `removed` lines from `start`
+    /// were replaced by `inserted` new ones.
+    fn bump_revision(&mut self, start: usize, removed: usize, inserted: usize) {
+        if self.splices.len() == SPLICE_LOG {
+            self.splices.pop_front();
+        }
+        self.splices.push_back((self.revision, start, removed, inserted));
+        self.revision = next_revision();
+    }
+
+    pub fn handle_input(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+        let word = modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+        let (row, col) = self.cursor;
+        if !matches!(code, KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown) {
+            self.preferred_col = None;
+        }
+        match code {
+            KeyCode::Char(c) if !word => self.insert_char(c),
+            KeyCode::Tab => {
+                let cell = LineLayout::cell_at_col(&self.line(row), col, self.tab_width);
+                let width = self.tab_width - cell % self.tab_width;
+                self.edit(self.cursor, self.cursor, " ".repeat(width), (row, col + width), false);
+            }
+            KeyCode::Enter => self.edit(self.cursor, self.cursor, "\n".to_string(), (row + 1, 0), false),
+            KeyCode::Backspace if word => self.delete_to(self.word_left(self.cursor)),
+            KeyCode::Backspace => self.delete_to(self.left_of(self.cursor)),
+            KeyCode::Delete if word => self.delete_to(self.word_right(self.cursor)),
+            KeyCode::Delete => self.delete_to(self.right_of(self.cursor)),
+            KeyCode::Left if word => self.cursor = self.word_left(self.cursor),
+            KeyCode::Left => self.cursor = self.left_of(self.cursor),
+            KeyCode::Right if word => self.cursor = self.word_right(self.cursor),
+            KeyCode::Right => self.cursor = self.right_of(self.cursor),
+            KeyCode::Up => self.move_vertical(-1),
+            KeyCode::Down => self.move_vertical(1),
+            KeyCode::PageUp => self.move_vertical(-(self.page_height.max(1) as isize)),
+            KeyCode::PageDown => self.move_vertical(self.page_height.max(1) as isize),
+            KeyCode::Home if ctrl => self.cursor = (0, 0),
+            KeyCode::Home => self.cursor = (row, 0),
+            KeyCode::End if ctrl => {
+                let last = self.line_count() - 1;
+                self.cursor = (last, self.line_len(last));
+            }
+            KeyCode::End => self.cursor = (row, self.line_len(row)),
+            _ => {}
+        }
+    }
+
+    fn insert_char(&mut self, c: char) {
+        let (row, col) = self.cursor;
+        // Group runs of typed word characters into a single undo step
+        self.edit(self.cursor, self.cursor, c.to_string(), (row, col + 1), !c.is_whitespace());
+    }
+
+    /// Delete to the end of the line, or the line break when already there.
+    pub fn delete_to_line_end(&mut self) {
+        self.delete_to(self.line_end_or_next(self.cursor));
+    }
+
+    /// Delete the text between the cursor and `to`, which may lie on
+    /// either side of it.
+    fn delete_to(&mut self, to: (usize, usize)) {
+        let (from, to) = if to < self.cursor { (to, self.cursor) } else { (self.cursor, to) };
+        if from == to {
             return;
         }
-        self.content_hash = new_hash;
+        self.edit(from, to, String::new(), from, false);
+    }
 
-        let extension = self.extension().unwrap_or_default();
-        let syntax = self
-            .syntax_set
-            .find_syntax_by_extension(&extension)
-            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+    /// Char offset of a (row, column) position.
+    fn char_idx(&self, (row, col): (usize, usize)) -> usize {
+        self.text.line_to_char(row) + col
+    }
 
-        let mut highlighter = HighlightLines::new(syntax, &self.theme);
-        let mut styled_lines: Vec<Vec<(Style, String)>> = Vec::new();
-        let mut line_lengths: Vec<usize> = Vec::new();
+    /// Replace the text between positions `from` and `to` with `new` as an
+    /// undoable step.
+    fn edit(
+        &mut self,
+        from: (usize, usize),
+        to: (usize, usize),
+        new: String,
+        cursor_after: (usize, usize),
+        coalesce: bool,
+    ) {
+        let at = self.char_idx(from);
+        let old = self.text.slice(at..self.char_idx(to)).to_string();
+        let edit = Edit { at, old, new };
+        let cursor_before = self.cursor;
+        self.apply_edit(&edit);
+        self.history.record(edit, cursor_before, cursor_after, coalesce);
+        self.set_cursor(cursor_after);
+    }
 
-        for line in LinesWithEndings::from(&content) {
-            let ranges = highlighter
-                .highlight_line(line, &self.syntax_set)
-                .unwrap_or_default();
+    /// Change the text, telling the highlighter which lines moved.
+    fn apply_edit(&mut self, edit: &Edit) {
+        let start = self.text.char_to_line(edit.at.min(self.text.len_chars()));
+        edit.apply(&mut self.text);
+        let lines = |text: &str| text.matches('\n').count() + 1;
+        let (removed, inserted) = (lines(&edit.old), lines(&edit.new));
+        self.highlight.splice(start, removed, inserted);
+        self.bump_revision(start, removed, inserted);
+    }
 
-            let mut spans: Vec<(Style, String)> = Vec::new();
-            let mut line_len = 0;
+    /// One grapheme cluster back, or the end of the previous line.
+    fn left_of(&self, (row, col): (usize, usize)) -> (usize, usize) {
+        if col > 0 {
+            let mut prev = 0;
+            let mut start = 0;
+            for grapheme in self.line(row).graphemes(true) {
+                if start >= col {
+                    break;
+                }
+                prev = start;
+                start += grapheme.chars().count();
+            }
+            (row, prev)
+        } else if row > 0 {
+            (row - 1, self.line_len(row - 1))
+        } else {
+            (row, col)
+        }
+    }
 
-            for (style, text) in ranges {
-                let cleaned = text.trim_end_matches('\n');
-                if cleaned.is_empty() {
-                    continue;
+    /// One grapheme cluster on, or the start of the next line.
+    fn right_of(&self, (row, col): (usize, usize)) -> (usize, usize) {
+        if col < self.line_len(row) {
+            let mut end = 0;
+            for grapheme in self.line(row).graphemes(true) {
+                end += grapheme.chars().count();
+                if end > col {
+                    break;
                 }
-                line_len += cleaned.chars().count();
-                spans.push((syntect_to_ratatui_style(style), cleaned.to_string()));
             }
+            (row, end)
+        } else if row + 1 < self.line_count() {
+            (row + 1, 0)
+        } else {
+            (row, col)
+        }
+    }
 
-            styled_lines.push(spans);
-            line_lengths.push(line_len);
+    /// End of the line, or the start of the next one when already there.
+    fn line_end_or_next(&self, (row, col): (usize, usize)) -> (usize, usize) {
+        let len = self.line_len(row);
+        if col < len {
+            (row, len)
+        } else {
+            self.right_of((row, col))
         }
+    }
 
-        if styled_lines.is_empty() {
-            styled_lines.push(Vec::new());
-            line_lengths.push(0);
+    /// Start of the word before `pos`, skipping whitespace first.
+    fn word_left(&self, (row, col): (usize, usize)) -> (usize, usize) {
+        if col == 0 {
+            return self.left_of((row, col));
+        }
+        let chars: Vec<char> = self.line(row).chars().collect();
+        let mut col = col.min(chars.len());
+        while col > 0 && chars[col - 1].is_whitespace() {
+            col -= 1;
         }
+        if col > 0 {
+            let class = char_class(chars[col - 1]);
+            while col > 0 && char_class(chars[col - 1]) == class {
+                col -= 1;
+            }
+        }
+        (row, col)
+    }
 
-        self.highlighted_lines = styled_lines;
-        self.line_lengths = line_lengths;
+    /// End of the word after `pos`, skipping whitespace first.
+    fn word_right(&self, (row, col): (usize, usize)) -> (usize, usize) {
+        let chars: Vec<char> = self.line(row).chars().collect();
+        if col >= chars.len() {
+            return self.right_of((row, col));
+        }
+        let mut col = col;
+        while col < chars.len() && chars[col].is_whitespace() {
+            col += 1;
+        }
+        if col < chars.len() {
+            let class = char_class(chars[col]);
+            while col < chars.len() && char_class(chars[col]) == class {
+                col += 1;
+            }
+        }
+        (row, col)
     }
 
-    pub fn highlighted_lines(&self) -> &[Vec<(Style, String)>] {
-        &self.highlighted_lines
+    /// Move up or down, keeping the display column so the cursor lines up
+    /// across tabs and wide characters.
+    fn move_vertical(&mut self, delta: isize) {
+        let (row, col) = self.cursor;
+        let target = self
+            .preferred_col
+            .unwrap_or_else(|| LineLayout::cell_at_col(&self.line(row), col, self.tab_width));
+        let row = row
+            .saturating_add_signed(delta)
+            .min(self.line_count() - 1);
+        self.cursor = (row, LineLayout::col_at_cell(&self.line(row), target, self.tab_width));
+        self.preferred_col = Some(target);
     }
 
-    pub fn line_lengths(&self) -> &[usize] {
-        &self.line_lengths
+    pub fn undo(&mut self) -> bool {
+        match self.history.undo() {
+            Some((edits, cursor)) => {
+                self.apply_edits(&edits, cursor);
+                true
+            }
+            None => false,
+        }
     }
 
-    pub fn handle_input(&mut self, code: KeyCode, modifiers: KeyModifiers) {
-        let input = crossterm::event::KeyEvent::new(code, modifiers);
-        self.textarea.input(input);
-        if is_navigation_key(code) {
-            return;
+    pub fn redo(&mut self) -> bool {
+        match self.history.redo() {
+            Some((edits, cursor)) => {
+                self.apply_edits(&edits, cursor);
+                true
+            }
+            None => false,
         }
-        self.update_highlighting();
     }
 
-    pub fn save(&mut self) -> Result<()> {
-        let content = self.textarea.lines().join("\n");
-        fs::write(&self.path, &content)?;
-        self.original_hash = simple_hash(&content);
-        self.content_hash = self.original_hash;
-        self.modified = false;
-        Ok(())
+    fn apply_edits(&mut self, edits: &[Edit], cursor: (usize, usize)) {
+        for edit in edits {
+            self.apply_edit(edit);
+        }
+        self.set_cursor(cursor);
     }
 
-    pub fn is_modified(&self) -> bool {
-        self.modified
+    /// (undoable steps, redoable steps) from the current state.
+    pub fn undo_status(&self) -> (usize, usize) {
+        (self.history.undo_depth(), self.history.redo_depth())
     }
 
-    /// Reload file from disk, preserving cursor position if possible
-    pub fn reload(&mut self) -> Result<()> {
-        let content = fs::read_to_string(&self.path)?;
-        let cursor = self.textarea.cursor();
+    /// Write the undo history to the state directory.
+    pub fn persist_history(&mut self) -> Result<()> {
+        self.history
+            .set_content_hash(hash_chunks(self.text.chunks()));
+        undo::store(&self.path, &self.history)
+    }
 
-        let lines: Vec<String> = content.lines().map(String::from).collect();
-        self.textarea = TextArea::new(lines);
-        self.textarea.set_cursor_line_style(ratatui::style::Style::default());
-        self.textarea.set_line_number_style(ratatui::style::Style::default().fg(ratatui::style::Color::DarkGray));
+    /// Consume the editor, returning its path and undo history so the
+    /// history can be reattached when the file is opened again.
+    pub fn into_history(mut self) -> (PathBuf, UndoTree) {
+        self.history
+            .set_content_hash(hash_chunks(self.text.chunks()));
+        (self.path, self.history)
+    }
 
-        // Try to restore cursor position
-        let max_row = self.textarea.lines().len().saturating_sub(1);
-        let row = cursor.0.min(max_row);
-        let max_col = self.textarea.lines().get(row).map(|l| l.len()).unwrap_or(0);
-        let col = cursor.1.min(max_col);
+    pub fn save(&mut self, backup: BackupMode) -> Result<(), SaveError> {
+        if self.format.mixed_endings {
+            return Err(SaveError::MixedLineEndings);
+        }
+        file_format::check_encodable(self.text.chunks(), &self.format)
+            .map_err(|e| SaveError::Encode(e.to_string()))?;
+        save::write_with(&self.path, backup, |out| {
+            file_format::write_text(self.text.chunks(), &self.format, out)
+        })?;
+        self.base = self.text.clone();
+        self.saved_format = self.format;
+        self.mark_saved();
+        self.saved_at = Some(Instant::now());
+        self.remove_swap();
+        // A shebang or modeline may have been typed since opening
+        self.detect_syntax();
+        Ok(())
+    }
 
-        // Move cursor to restored position
-        for _ in 0..row {
-            self.textarea.input(crossterm::event::KeyEvent::new(KeyCode::Down, KeyModifiers::NONE));
+    /// Save to `path` and make it the buffer's file from now on. The old
+    /// file's swap goes with it; the undo history stays with the buffer.
+    pub fn save_as(&mut self, path: PathBuf, backup: BackupMode) -> Result<(), SaveError> {
+        let previous = std::mem::replace(&mut self.path, path);
+        let swap_revision = self.swap_revision.take();
+        if let Err(e) = self.save(backup) {
+            self.path = previous;
+            self.swap_revision = swap_revision;
+            return Err(e);
         }
-        for _ in 0..col {
-            self.textarea.input(crossterm::event::KeyEvent::new(KeyCode::Right, KeyModifiers::NONE));
+        if swap_revision.is_some() {
+            swap::remove(&previous);
         }
+        Ok(())
+    }
+
+    /// Record the current text as matching the disk.
+    fn mark_saved(&mut self) {
+        self.saved_node = Some(self.history.current());
+        self.history.seal();
+    }
+
+    /// When the buffer was last saved this session, if it was.
+    pub fn saved_at(&self) -> Option<Instant> {
+        self.saved_at
+    }
 
-        self.original_hash = simple_hash(&content);
-        self.modified = false;
-        self.content_hash = 0;
-        self.update_highlighting();
+    /// Write unsaved text to the swap file if it changed since the last
+    /// write, or drop the swap once the buffer matches the disk again.
+    pub fn write_swap(&mut self) -> Result<()> {
+        if !self.is_modified() {
+            self.remove_swap();
+            return Ok(());
+        }
+        if self.swap_revision == Some(self.revision) {
+            return Ok(());
+        }
+        swap::write(&self.path, &self.text)?;
+        self.swap_revision = Some(self.revision);
         Ok(())
     }
 
+    pub fn remove_swap(&mut self) {
+        if self.swap_revision.take().is_some() {
+            swap::remove(&self.path);
+        }
+    }
+
+    pub fn base_lines(&self) -> Vec<String> {
+        text::to_lines(&self.base)
+    }
+
+    /// True if `lines` are the text last loaded from or saved to disk.
+    pub fn is_base(&self, lines: &[String]) -> bool {
+        self.base == text::from_lines(lines)
+    }
+
+    /// Adopt `theirs` (the current disk text) as the new base and replace
+    /// the buffer with the merge result.
+    pub fn apply_merge(&mut self, theirs: Vec<String>, merged: Vec<String>) {
+        let matches_disk = merged == theirs;
+        self.base = text::from_lines(&theirs);
+        self.replace_contents(merged);
+        if matches_disk {
+            self.mark_saved();
+        } else {
+            self.saved_node = None;
+        }
+    }
+
+    /// Replace the whole buffer as a single undoable step, keeping the cursor.
+    pub fn replace_contents(&mut self, lines: Vec<String>) {
+        let cursor = self.cursor;
+        if let Some(edit) = Edit::between(&self.text, &text::from_lines(&lines)) {
+            self.apply_edit(&edit);
+            self.history.record(edit, cursor, cursor, false);
+            self.set_cursor(cursor);
+        }
+    }
+
+    /// Replace the text between positions `from` and `to` as a single
+    /// undoable step, keeping the cursor.
+    pub fn replace_range(&mut self, from: (usize, usize), to: (usize, usize), with: String) {
+        self.edit(from, to, with, self.cursor, false);
+    }
+
+    /// True when the text or its on-disk format differs from the last save.
+    pub fn is_modified(&self) -> bool {
+        self.saved_node != Some(self.history.current()) || self.format != self.saved_format
+    }
+
+    // Format changes count as edits too: they bump the revision so idle
+    // autosave waits for a pause after them like after typing
+    /// Switch between LF and CRLF. A file mixing both first settles on the
+    /// style most of its lines use.
+    pub fn toggle_line_ending(&mut self) {
+        if self.format.mixed_endings {
+            self.format.mixed_endings = false;
+        } else {
+            self.format.line_ending = self.format.line_ending.toggled();
+        }
+        self.bump_revision(0, 0, 0);
+    }
+
+    pub fn toggle_final_newline(&mut self) {
+        self.format.final_newline = !self.format.final_newline;
+        self.bump_revision(0, 0, 0);
+    }
+
+    pub fn cycle_encoding(&mut self) {
+        self.format.encoding = self.format.encoding.next();
+        self.bump_revision(0, 0, 0);
+    }
+
+    /// Reload file from disk, preserving cursor position if possible.
+    /// The reload is recorded as an undoable step.
+    pub fn reload(&mut self) -> Result<()> {
+        let (text, format) = file_format::decode_text(&fs::read(&self.path)?)?;
+        let text = Rope::from_str(&text);
+        self.replace_contents(text::to_lines(&text));
+        self.base = text;
+        self.format = format;
+        self.saved_format = format;
+        self.mark_saved();
+        self.detect_syntax();
+        Ok(())
+    }
 
     pub fn filename(&self) -> String {
         self.path
@@ -171,58 +620,49 @@ impl Editor {
             .unwrap_or_else(|| "untitled".into())
     }
 
-    pub fn extension(&self) -> Option<String> {
-        self.path
-            .extension()
-            .map(|s| s.to_string_lossy().to_string())
-    }
-
     pub fn cursor_position(&self) -> (usize, usize) {
-        self.textarea.cursor()
+        self.cursor
     }
 
-    pub fn scroll_up(&mut self, lines: usize) {
-        for _ in 0..lines {
-            self.textarea.input(crossterm::event::KeyEvent::new(
-                KeyCode::Up,
-                KeyModifiers::NONE,
-            ));
-        }
+    /// Move the cursor, clamped to the text.
+    pub fn set_cursor(&mut self, (row, col): (usize, usize)) {
+        self.preferred_col = None;
+        let row = row.min(self.line_count() - 1);
+        self.cursor = (row, col.min(self.line_len(row)));
     }
+}
 
-    pub fn scroll_down(&mut self, lines: usize) {
-        for _ in 0..lines {
-            self.textarea.input(crossterm::event::KeyEvent::new(
-                KeyCode::Down,
-                KeyModifiers::NONE,
-            ));
-        }
-    }
+/// Revisions are drawn from one counter shared by all buffers, so equal
+/// revisions always mean the same buffer in the same state.
+fn next_revision() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
 }
 
-fn syntect_to_ratatui_style(style: SyntectStyle) -> Style {
-    let fg = Color::Rgb(style.foreground.r, style.foreground.g, style.foreground.b);
-    Style::default().fg(fg)
+/// Words are runs of chars of the same class; whitespace separates them.
+fn char_class(c: char) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
 }
 
-fn simple_hash(s: &str) -> u64 {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    let mut hasher = DefaultHasher::new();
-    s.hash(&mut hasher);
-    hasher.finish()
+/// FNV-1a; stable across runs and toolchains, so it can key on-disk state.
+pub(crate) fn simple_hash(s: &str) -> u64 {
+    hash_chunks([s])
 }
 
-fn is_navigation_key(code: KeyCode) -> bool {
-    matches!(
-        code,
-        KeyCode::Up
-            | KeyCode::Down
-            | KeyCode::Left
-            | KeyCode::Right
-            | KeyCode::Home
-            | KeyCode::End
-            | KeyCode::PageUp
-            | KeyCode::PageDown
-    )
+/// `simple_hash` of the concatenation of `chunks`, e.g. a rope's.
+pub(crate) fn hash_chunks<'a>(chunks: impl IntoIterator<Item = &'a str>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for chunk in chunks {
+        for byte in chunk.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
 }
@@ -0,0 +1,349 @@
+use anyhow::{bail, Result};
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+        }
+    }
+
+    pub fn toggled(self) -> Self {
+        match self {
+            LineEnding::Lf => LineEnding::CrLf,
+            LineEnding::CrLf => LineEnding::Lf,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl Encoding {
+    pub fn label(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf8Bom => "UTF-8 BOM",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "Latin-1",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Encoding::Utf8 => Encoding::Utf8Bom,
+            Encoding::Utf8Bom => Encoding::Utf16Le,
+            Encoding::Utf16Le => Encoding::Utf16Be,
+            Encoding::Utf16Be => Encoding::Latin1,
+            Encoding::Latin1 => Encoding::Utf8,
+        }
+    }
+}
+
+/// How a file was stored on disk, so it can be written back byte-for-byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileFormat {
+    pub line_ending: LineEnding,
+    pub final_newline: bool,
+    pub encoding: Encoding,
+    /// Both CRLF and LF were found. `line_ending` is the one most lines
+    /// use, and encoding gives it to every line.
+    pub mixed_endings: bool,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            line_ending: LineEnding::Lf,
+            final_newline: true,
+            encoding: Encoding::Utf8,
+            mixed_endings: false,
+        }
+    }
+}
+
+impl FileFormat {
+    /// Short description for the status bar, e.g. `UTF-8 CRLF ¶`.
+    pub fn describe(&self) -> String {
+        format!(
+            "{} {}{}",
+            self.encoding.label(),
+            if self.mixed_endings { "CRLF+LF" } else { self.line_ending.label() },
+            if self.final_newline { " ¶" } else { " no-EOL" }
+        )
+    }
+}
+
+/// Decode raw file bytes into lines, detecting the encoding, line-ending
+/// style and whether the file ends with a newline.
+pub fn decode(bytes: &[u8]) -> Result<(Vec<String>, FileFormat)> {
+    let (text, format) = decode_text(bytes)?;
+    Ok((text.split('\n').map(String::from).collect(), format))
+}
+
+/// Like `decode`, but returns the text as one string with lines separated
+/// by a bare `\n` and no final newline, ready to build a rope from.
+pub fn decode_text(bytes: &[u8]) -> Result<(String, FileFormat)> {
+    let (mut text, encoding) = if let Some(rest) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        (String::from_utf8(rest.to_vec())?, Encoding::Utf8Bom)
+    } else if let Some(rest) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        (decode_utf16(rest, u16::from_le_bytes)?, Encoding::Utf16Le)
+    } else if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        (decode_utf16(rest, u16::from_be_bytes)?, Encoding::Utf16Be)
+    } else {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => (text.to_string(), Encoding::Utf8),
+            // Every byte sequence is valid Latin-1
+            Err(_) => (bytes.iter().map(|&b| b as char).collect(), Encoding::Latin1),
+        }
+    };
+
+    // Mixed files are read with whichever style is in the majority and
+    // flagged, since writing them back would convert the other lines
+    let crlf = text.matches("\r\n").count();
+    let lf = text.matches('\n').count() - crlf;
+    let line_ending = if crlf > lf {
+        LineEnding::CrLf
+    } else {
+        LineEnding::Lf
+    };
+    let final_newline = text.ends_with('\n');
+
+    if crlf > 0 {
+        text = text.replace("\r\n", "\n");
+    }
+    if final_newline {
+        text.pop();
+    }
+    Ok((
+        text,
+        FileFormat {
+            line_ending,
+            final_newline,
+            encoding,
+            mixed_endings: crlf > 0 && lf > 0,
+        },
+    ))
+}
+
+/// Encode lines back to bytes using `format`.
+pub fn encode(lines: &[String], format: &FileFormat) -> Result<Vec<u8>> {
+    encode_text([lines.join("\n").as_str()], format)
+}
+
+/// Encode `\n`-separated text, given as consecutive pieces such as a
+/// rope's chunks, back to bytes using `format`.
+pub fn encode_text<'a>(chunks: impl IntoIterator<Item = &'a str> + Clone, format: &FileFormat) -> Result<Vec<u8>> {
+    check_encodable(chunks.clone(), format)?;
+    let mut out = Vec::new();
+    write_text(chunks, format, &mut out)?;
+    Ok(out)
+}
+
+/// Fail naming the first character that `format`'s encoding cannot hold.
+pub fn check_encodable<'a>(chunks: impl IntoIterator<Item = &'a str>, format: &FileFormat) -> Result<()> {
+    if format.encoding == Encoding::Latin1 {
+        if let Some(c) = chunks.into_iter().flat_map(str::chars).find(|&c| c as u32 > 0xFF) {
+            bail!("'{}' cannot be encoded as Latin-1", c);
+        }
+    }
+    Ok(())
+}
+
+/// Like `encode_text`, but encodes one chunk at a time straight into `out`.
+/// Run `check_encodable` first: a character the encoding cannot hold
+/// fails the write halfway.
+pub fn write_text<'a>(
+    chunks: impl IntoIterator<Item = &'a str>,
+    format: &FileFormat,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let bom: &[u8] = match format.encoding {
+        Encoding::Utf8 | Encoding::Latin1 => &[],
+        Encoding::Utf8Bom => &[0xEF, 0xBB, 0xBF],
+        Encoding::Utf16Le => &[0xFF, 0xFE],
+        Encoding::Utf16Be => &[0xFE, 0xFF],
+    };
+    out.write_all(bom)?;
+    for chunk in chunks {
+        match format.line_ending {
+            LineEnding::Lf => write_encoded(chunk, format.encoding, out)?,
+            LineEnding::CrLf => {
+                for (idx, piece) in chunk.split('\n').enumerate() {
+                    if idx > 0 {
+                        write_encoded("\r\n", format.encoding, out)?;
+                    }
+                    write_encoded(piece, format.encoding, out)?;
+                }
+            }
+        }
+    }
+    if format.final_newline {
+        write_encoded(format.line_ending.as_str(), format.encoding, out)?;
+    }
+    Ok(())
+}
+
+fn write_encoded(text: &str, encoding: Encoding, out: &mut dyn Write) -> io::Result<()> {
+    match encoding {
+        Encoding::Utf8 | Encoding::Utf8Bom => out.write_all(text.as_bytes()),
+        Encoding::Utf16Le => {
+            let bytes: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            out.write_all(&bytes)
+        }
+        Encoding::Utf16Be => {
+            let bytes: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+            out.write_all(&bytes)
+        }
+        Encoding::Latin1 => {
+            let mut bytes = Vec::with_capacity(text.len());
+            for c in text.chars() {
+                let byte = u8::try_from(c).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("'{}' cannot be encoded as Latin-1", c))
+                })?;
+                bytes.push(byte);
+            }
+            out.write_all(&bytes)
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Result<String> {
+    if !bytes.len().is_multiple_of(2) {
+        bail!("truncated UTF-16 data");
+    }
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| from_bytes([pair[0], pair[1]]))
+        .collect();
+    Ok(String::from_utf16(&units)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode `bytes` and encode the text straight back.
+    fn round_trip(bytes: &[u8]) -> (String, FileFormat, Vec<u8>) {
+        let (text, format) = decode_text(bytes).unwrap();
+        let encoded = encode_text([text.as_str()], &format).unwrap();
+        (text, format, encoded)
+    }
+
+    #[test]
+    fn lf_with_final_newline_round_trips() {
+        let (text, format, encoded) = round_trip(b"one\ntwo\n");
+        assert_eq!(text, "one\ntwo");
+        assert_eq!(format, FileFormat::default());
+        assert_eq!(encoded, b"one\ntwo\n");
+    }
+
+    #[test]
+    fn crlf_round_trips() {
+        let (text, format, encoded) = round_trip(b"one\r\ntwo\r\n");
+        assert_eq!(text, "one\ntwo");
+        assert_eq!(format.line_ending, LineEnding::CrLf);
+        assert!(format.final_newline);
+        assert_eq!(encoded, b"one\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn mixed_endings_are_flagged_and_follow_the_majority() {
+        let (text, format, encoded) = round_trip(b"one\r\ntwo\nthree\r\n");
+        assert_eq!(text, "one\ntwo\nthree");
+        assert_eq!(format.line_ending, LineEnding::CrLf);
+        assert!(format.mixed_endings);
+        assert_eq!(format.describe(), "UTF-8 CRLF+LF ¶");
+        assert_eq!(encoded, b"one\r\ntwo\r\nthree\r\n");
+
+        let (_, format, encoded) = round_trip(b"one\ntwo\r\nthree\n");
+        assert_eq!(format.line_ending, LineEnding::Lf);
+        assert!(format.mixed_endings);
+        assert_eq!(encoded, b"one\ntwo\nthree\n");
+
+        let (_, format, _) = round_trip(b"one\r\ntwo");
+        assert!(!format.mixed_endings);
+    }
+
+    #[test]
+    fn missing_final_newline_is_kept() {
+        let (text, format, encoded) = round_trip(b"one\r\ntwo");
+        assert_eq!(text, "one\ntwo");
+        assert!(!format.final_newline);
+        assert_eq!(encoded, b"one\r\ntwo");
+
+        let (text, format, encoded) = round_trip(b"");
+        assert_eq!(text, "");
+        assert!(!format.final_newline);
+        assert_eq!(encoded, b"");
+    }
+
+    #[test]
+    fn encodings_round_trip() {
+        for bytes in [
+            &b"\xEF\xBB\xBFcaf\xC3\xA9\n"[..],
+            &b"\xFF\xFEc\0a\0f\0\xE9\0\n\0"[..],
+            &b"\xFE\xFF\0c\0a\0f\0\xE9\0\n"[..],
+            &b"caf\xE9\n"[..],
+        ] {
+            let (text, _, encoded) = round_trip(bytes);
+            assert_eq!(text, "café");
+            assert_eq!(encoded, bytes);
+        }
+        assert_eq!(decode_text(b"caf\xE9").unwrap().1.encoding, Encoding::Latin1);
+    }
+
+    #[test]
+    fn encoding_follows_chunks_and_format_changes() {
+        let format = FileFormat {
+            line_ending: LineEnding::CrLf,
+            final_newline: false,
+            ..FileFormat::default()
+        };
+        assert_eq!(encode_text(["on", "e\ntw", "o"], &format).unwrap(), b"one\r\ntwo");
+        let latin1 = FileFormat {
+            encoding: Encoding::Latin1,
+            ..format
+        };
+        assert!(encode_text(["日本"], &latin1).is_err());
+    }
+
+    #[test]
+    fn chunks_are_written_in_every_format() {
+        let chunks = ["on", "e\ntwo\n", "", "thré"];
+        for encoding in [Encoding::Utf8, Encoding::Utf8Bom, Encoding::Utf16Le, Encoding::Utf16Be, Encoding::Latin1] {
+            for line_ending in [LineEnding::Lf, LineEnding::CrLf] {
+                let format = FileFormat {
+                    line_ending,
+                    encoding,
+                    ..FileFormat::default()
+                };
+                let mut written = Vec::new();
+                write_text(chunks, &format, &mut written).unwrap();
+                let lines = vec!["one".to_string(), "two".to_string(), "thré".to_string()];
+                assert_eq!(decode(&written).unwrap(), (lines, format));
+            }
+        }
+    }
+}
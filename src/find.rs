@@ -0,0 +1,343 @@
+use anyhow::Result;
+use regex::{Regex, RegexBuilder};
+use std::ops::Range;
+
+use crate::editor::Editor;
+use crate::replace;
+use crate::text;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindField {
+    Query,
+    Replace,
+}
+
+/// Find / replace within the active buffer.
+pub struct FindBar {
+    pub query: String,
+    pub replacement: String,
+    pub regex: bool,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    /// Field receiving typed text
+    pub field: FindField,
+    pub show_replace: bool,
+    pub error: Option<String>,
+    /// Every match in the buffer as (row, byte range), in order
+    pub matches: Vec<(usize, Range<usize>)>,
+    /// Cursor when the bar opened; typing searches forward from here
+    origin: (usize, usize),
+    re: Option<Regex>,
+    /// Options `matches` was computed for
+    key: Option<(String, bool, bool, bool)>,
+    /// Buffer revision `matches` is up to date with
+    revision: u64,
+}
+
+impl FindBar {
+    pub fn new(origin: (usize, usize)) -> Self {
+        Self {
+            query: String::new(),
+            replacement: String::new(),
+            regex: false,
+            case_sensitive: false,
+            whole_word: false,
+            field: FindField::Query,
+            show_replace: false,
+            error: None,
+            matches: Vec::new(),
+            origin,
+            re: None,
+            key: None,
+            revision: 0,
+        }
+    }
+
+    fn build_regex(&self) -> Result<Regex> {
+        let mut pattern = if self.regex {
+            self.query.clone()
+        } else {
+            regex::escape(&self.query)
+        };
+        if self.whole_word {
+            pattern = format!(r"\b(?:{})\b", pattern);
+        }
+        Ok(RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .build()?)
+    }
+
+    /// Recompute `matches` if the buffer or the search options changed.
+    /// After edits only the lines they touched are searched again.
+    pub fn refresh(&mut self, editor: &Editor) {
+        let key = (self.query.clone(), self.regex, self.case_sensitive, self.whole_word);
+        if self.key.as_ref() == Some(&key) {
+            if let Some(splices) = editor.splices_since(self.revision) {
+                self.revision = editor.revision();
+                self.rescan(editor, splices);
+                return;
+            }
+        }
+        self.key = Some(key);
+        self.revision = editor.revision();
+        self.matches.clear();
+        self.re = None;
+        self.error = None;
+        if self.query.is_empty() {
+            return;
+        }
+        let re = match self.build_regex() {
+            Ok(re) => re,
+            Err(e) => {
+                self.error = Some(e.to_string());
+                return;
+            }
+        };
+        for (row, line) in text::lines(editor.text()).enumerate() {
+            self.matches.extend(
+                re.find_iter(&line)
+                    .filter(|m| !m.is_empty())
+                    .map(|m| (row, m.range())),
+            );
+        }
+        self.re = Some(re);
+    }
+
+    /// Follow `matches` through line splices, then search the lines they
+    /// inserted.
+    fn rescan(&mut self, editor: &Editor, splices: impl Iterator<Item = (usize, usize, usize)>) {
+        let Some(ref re) = self.re else {
+            return;
+        };
+        // Lines still to search, in the current numbering
+        let mut dirty: Option<Range<usize>> = None;
+        for (start, removed, inserted) in splices {
+            let end = start + removed;
+            self.matches.retain(|(row, _)| *row < start || *row >= end);
+            for (row, _) in &mut self.matches {
+                if *row >= end {
+                    *row = *row - removed + inserted;
+                }
+            }
+            let moved = |row: usize, inside: usize| match row {
+                row if row < start => row,
+                row if row >= end => row - removed + inserted,
+                _ => inside,
+            };
+            dirty = dirty.map(|d| moved(d.start, start)..moved(d.end, start + inserted));
+            if inserted > 0 {
+                dirty = Some(match dirty {
+                    Some(d) => d.start.min(start)..d.end.max(start + inserted),
+                    None => start..start + inserted,
+                });
+            }
+        }
+        let Some(dirty) = dirty else {
+            return;
+        };
+        let dirty = dirty.start..dirty.end.min(editor.line_count());
+        self.matches.retain(|(row, _)| !dirty.contains(row));
+        let mut found = Vec::new();
+        for row in dirty.clone() {
+            let line = editor.line(row);
+            found.extend(
+                re.find_iter(&line)
+                    .filter(|m| !m.is_empty())
+                    .map(|m| (row, m.range())),
+            );
+        }
+        let at = self.matches.partition_point(|(row, _)| *row < dirty.start);
+        self.matches.splice(at..at, found);
+    }
+
+    /// Char-based cursor position of the start of match `idx`.
+    fn position(&self, idx: usize, editor: &Editor) -> (usize, usize) {
+        let (row, ref range) = self.matches[idx];
+        let line = editor.line(row);
+        let col = line.get(..range.start).map_or(0, |l| l.chars().count());
+        (row, col)
+    }
+
+    /// The match the cursor sits on, if any.
+    pub fn current(&self, editor: &Editor) -> Option<usize> {
+        let cursor = editor.cursor_position();
+        let first = self.matches.partition_point(|(row, _)| *row < cursor.0);
+        (first..self.matches.len())
+            .take_while(|&idx| self.matches[idx].0 == cursor.0)
+            .find(|&idx| self.position(idx, editor) == cursor)
+    }
+
+    /// Move the cursor to the next (or previous) match, wrapping around the
+    /// buffer. `inclusive` accepts a match right at the cursor.
+    pub fn jump(&self, editor: &mut Editor, forward: bool, inclusive: bool) -> bool {
+        self.jump_from(editor, editor.cursor_position(), forward, inclusive)
+    }
+
+    /// Jump to the first match at or after where the bar was opened, for
+    /// search-as-you-type.
+    pub fn jump_from_origin(&self, editor: &mut Editor) -> bool {
+        self.jump_from(editor, self.origin, true, true)
+    }
+
+    fn jump_from(&self, editor: &mut Editor, from: (usize, usize), forward: bool, inclusive: bool) -> bool {
+        if self.matches.is_empty() {
+            return false;
+        }
+        // Compare in bytes, as `matches` holds them, so only the target's
+        // position needs converting
+        let line = editor.line(from.0);
+        let from = (from.0, line.char_indices().nth(from.1).map_or(line.len(), |(at, _)| at));
+        // Matches before the target going forward, or up to and including
+        // it going back
+        let split = self.matches.partition_point(|(row, range)| {
+            let start = (*row, range.start);
+            start < from || (start == from && inclusive != forward)
+        });
+        let target = if forward {
+            if split == self.matches.len() {
+                0
+            } else {
+                split
+            }
+        } else {
+            split.checked_sub(1).unwrap_or(self.matches.len() - 1)
+        };
+        editor.set_cursor(self.position(target, editor));
+        true
+    }
+
+    /// Replace the match under the cursor and move on to the next one. If
+    /// the cursor is not on a match, only moves to the next.
+    pub fn replace_current(&mut self, editor: &mut Editor) -> bool {
+        let (Some(idx), Some(re)) = (self.current(editor), self.re.as_ref()) else {
+            return self.jump(editor, true, true);
+        };
+        let (row, ref range) = self.matches[idx];
+        let text = editor.line(row).into_owned();
+        let Some((end, with)) = replace::substitute(re, &text, range.start, &self.replacement, self.regex) else {
+            return false;
+        };
+        let start = text[..range.start].chars().count();
+        let after = start + with.chars().count();
+        editor.replace_range((row, start), (row, start + text[range.start..end].chars().count()), with);
+        editor.set_cursor((row, after));
+        self.refresh(editor);
+        self.jump(editor, true, true);
+        true
+    }
+
+    /// Replace every match as a single undo step. Returns how many were
+    /// replaced.
+    pub fn replace_all(&mut self, editor: &mut Editor) -> usize {
+        let Some(ref re) = self.re else {
+            return 0;
+        };
+        let mut lines = editor.lines();
+        let mut count = 0;
+        for row_matches in self.matches.chunk_by(|a, b| a.0 == b.0) {
+            let row = row_matches[0].0;
+            let text = &lines[row];
+            let mut new_text = String::with_capacity(text.len());
+            let mut pos = 0;
+            for (_, range) in row_matches {
+                if range.start < pos {
+                    continue;
+                }
+                if let Some((end, with)) =
+                    replace::substitute(re, text, range.start, &self.replacement, self.regex)
+                {
+                    new_text.push_str(&text[pos..range.start]);
+                    new_text.push_str(&with);
+                    pos = end;
+                    count += 1;
+                }
+            }
+            new_text.push_str(&text[pos..]);
+            lines[row] = new_text;
+        }
+        if count > 0 {
+            editor.replace_contents(lines);
+            self.refresh(editor);
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use syntect::highlighting::Theme;
+    use syntect::parsing::SyntaxSet;
+
+    /// An editor on a scratch file holding `text`.
+    fn editor(name: &str, text: &str) -> Editor {
+        let path = std::env::temp_dir().join(format!("teditor-find-{}-{}.txt", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let syntax_set = Arc::new(SyntaxSet::load_defaults_newlines());
+        let editor = Editor::open(PathBuf::from(&path), None, syntax_set, Arc::new(Theme::default())).unwrap();
+        let _ = std::fs::remove_file(path);
+        editor
+    }
+
+    fn find(editor: &Editor, query: &str) -> FindBar {
+        let mut find = FindBar::new((0, 0));
+        find.query = query.to_string();
+        find.refresh(editor);
+        find
+    }
+
+    #[test]
+    fn edits_update_matches_like_a_full_search() {
+        let mut editor = editor("rescan", "foo a\nbar\nfoo foo\nbaz\nfoo");
+        let mut bar = find(&editor, "foo");
+        let edits = [
+            ((1, 0), (1, 0), "foo\nnew foo\n"),
+            ((0, 0), (2, 3), "x"),
+            ((2, 4), (2, 7), "fo"),
+            ((3, 0), (4, 3), ""),
+            ((0, 1), (0, 1), "foo"),
+        ];
+        for (from, to, with) in edits {
+            editor.replace_range(from, to, with.to_string());
+            editor.toggle_final_newline();
+            bar.refresh(&editor);
+            assert_eq!(bar.matches, find(&editor, "foo").matches, "after {:?}", with);
+        }
+        editor.undo();
+        editor.undo();
+        bar.refresh(&editor);
+        assert_eq!(bar.matches, find(&editor, "foo").matches);
+    }
+
+    #[test]
+    fn jumps_wrap_around_the_buffer() {
+        let mut editor = editor("jump", "ab ab\néab\nab");
+        let bar = find(&editor, "ab");
+        let mut visit = |forward, inclusive| {
+            bar.jump(&mut editor, forward, inclusive);
+            editor.cursor_position()
+        };
+        assert_eq!(visit(true, true), (0, 0));
+        assert_eq!(visit(true, false), (0, 3));
+        assert_eq!(visit(true, false), (1, 1));
+        assert_eq!(visit(true, false), (2, 0));
+        assert_eq!(visit(true, false), (0, 0));
+        assert_eq!(visit(false, false), (2, 0));
+        assert_eq!(visit(false, false), (1, 1));
+        assert_eq!(visit(false, true), (1, 1));
+    }
+
+    #[test]
+    fn the_match_under_the_cursor_is_current() {
+        let mut editor = editor("current", "xéab ab");
+        let bar = find(&editor, "ab");
+        editor.set_cursor((0, 2));
+        assert_eq!(bar.current(&editor), Some(0));
+        editor.set_cursor((0, 3));
+        assert_eq!(bar.current(&editor), None);
+        editor.set_cursor((0, 5));
+        assert_eq!(bar.current(&editor), Some(1));
+    }
+}
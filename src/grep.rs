@@ -0,0 +1,426 @@
+use anyhow::Result;
+use ignore::WalkBuilder;
+use regex::{Regex, RegexBuilder};
+use ropey::Rope;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+use crate::file_format;
+use crate::replace;
+use crate::swap::DiffLine;
+use crate::text;
+
+/// Results beyond this are dropped and the search stopped.
+const MAX_RESULTS: usize = 10_000;
+
+/// Files with a NUL byte in their first this-many bytes are treated as binary.
+const BINARY_PROBE: usize = 8192;
+
+/// One line of a file that matches the content search.
+#[derive(Debug, Clone)]
+pub struct GrepMatch {
+    /// Path relative to the search root
+    pub path: PathBuf,
+    pub line: usize,
+    /// Char offset of the match start, the unit the editor's cursor uses
+    pub column: usize,
+    pub text: String,
+    /// Byte range of the match within `text`
+    pub range: Range<usize>,
+}
+
+/// Diff of what a replace would do to one file.
+pub struct Preview {
+    pub path: PathBuf,
+    pub diff: Vec<DiffLine>,
+    pub scroll: usize,
+}
+
+/// Content search across the project, run on a background thread.
+pub struct Grep {
+    pub query: String,
+    /// Treat `query` as a regex rather than literal text
+    pub regex: bool,
+    /// Replacement text while in replace mode
+    pub replacement: Option<String>,
+    /// Typing goes to `replacement` rather than `query`
+    pub replace_focus: bool,
+    pub results: Vec<GrepMatch>,
+    /// Indices into `results` left out of the replace
+    pub excluded: HashSet<usize>,
+    pub selected: usize,
+    pub searching: bool,
+    /// Stopped early at `MAX_RESULTS`
+    pub truncated: bool,
+    pub error: Option<String>,
+    pub preview: Option<Preview>,
+    re: Option<Regex>,
+    rx: Option<Receiver<Vec<GrepMatch>>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Grep {
+    pub fn new() -> Self {
+        Self {
+            query: String::new(),
+            regex: false,
+            replacement: None,
+            replace_focus: false,
+            results: Vec::new(),
+            excluded: HashSet::new(),
+            selected: 0,
+            searching: false,
+            truncated: false,
+            error: None,
+            preview: None,
+            re: None,
+            rx: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Restart the search for the current query, cancelling any run still
+    /// in progress. `open` holds the text of open buffers by relative path,
+    /// searched in place of the file on disk.
+    pub fn start(&mut self, root: &Path, show_hidden: bool, open: HashMap<PathBuf, Rope>) {
+        self.stop();
+        self.results.clear();
+        self.excluded.clear();
+        self.selected = 0;
+        self.truncated = false;
+        self.error = None;
+        self.re = None;
+        if self.query.is_empty() {
+            return;
+        }
+
+        let re = match build_regex(&self.query, self.regex) {
+            Ok(re) => re,
+            Err(e) => {
+                self.error = Some(e.to_string());
+                return;
+            }
+        };
+        self.re = Some(re.clone());
+        let (tx, rx) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let root = root.to_path_buf();
+        let worker_cancel = cancel.clone();
+        thread::spawn(move || search_tree(&root, show_hidden, &re, &open, &tx, &worker_cancel));
+        self.rx = Some(rx);
+        self.cancel = cancel;
+        self.searching = true;
+    }
+
+    fn stop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.rx = None;
+        self.searching = false;
+    }
+
+    /// Take in results streamed since the last call. Returns true if
+    /// anything changed.
+    pub fn poll(&mut self) -> bool {
+        let Some(ref rx) = self.rx else {
+            return false;
+        };
+        let mut changed = false;
+        loop {
+            match rx.try_recv() {
+                Ok(batch) => {
+                    self.results.extend(batch);
+                    changed = true;
+                    if self.results.len() >= MAX_RESULTS {
+                        self.results.truncate(MAX_RESULTS);
+                        self.truncated = true;
+                        self.stop();
+                        break;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.rx = None;
+                    self.searching = false;
+                    changed = true;
+                    break;
+                }
+            }
+        }
+        changed
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        let max = self.results.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(max);
+    }
+
+    pub fn selected_match(&self) -> Option<&GrepMatch> {
+        self.results.get(self.selected)
+    }
+
+    pub fn toggle_selected(&mut self) {
+        if self.selected < self.results.len() && !self.excluded.remove(&self.selected) {
+            self.excluded.insert(self.selected);
+        }
+    }
+
+    pub fn can_replace(&self) -> bool {
+        self.replacement.is_some() && self.re.is_some()
+    }
+
+    /// What the hit would become under the current replacement, if in
+    /// replace mode and it still matches.
+    pub fn replacement_for(&self, m: &GrepMatch) -> Option<String> {
+        let replacement = self.replacement.as_ref()?;
+        let re = self.re.as_ref()?;
+        replace::substitute(re, &m.text, m.range.start, replacement, self.regex).map(|(_, s)| s)
+    }
+
+    /// Replace `hits` within `lines` using the current pattern and
+    /// replacement. See `replace::replace_in_lines`.
+    pub fn apply_to(&self, lines: &[String], hits: &[&GrepMatch]) -> Option<(Vec<String>, usize, usize)> {
+        let replacement = self.replacement.as_ref()?;
+        let re = self.re.as_ref()?;
+        Some(replace::replace_in_lines(lines, hits, re, replacement, self.regex))
+    }
+
+    /// Hits not excluded from the replace, grouped by file.
+    pub fn included_by_file(&self) -> BTreeMap<&Path, Vec<&GrepMatch>> {
+        let mut files: BTreeMap<&Path, Vec<&GrepMatch>> = BTreeMap::new();
+        for (idx, m) in self.results.iter().enumerate() {
+            if !self.excluded.contains(&idx) {
+                files.entry(m.path.as_path()).or_default().push(m);
+            }
+        }
+        files
+    }
+}
+
+/// Compile the search pattern. Literal queries are escaped; both kinds are
+/// case-insensitive unless they contain an uppercase letter.
+pub fn build_regex(query: &str, regex: bool) -> Result<Regex> {
+    let pattern = if regex {
+        query.to_string()
+    } else {
+        regex::escape(query)
+    };
+    let case_insensitive = if regex {
+        !has_literal_uppercase(query)
+    } else {
+        !query.chars().any(char::is_uppercase)
+    };
+    Ok(RegexBuilder::new(&pattern)
+        .case_insensitive(case_insensitive)
+        .build()?)
+}
+
+/// Whether a regex asks for an uppercase letter by itself. Escapes such as
+/// `\S` or `\p{Lu}` and anything inside `[...]` don't count, so `\w+` and
+/// `[A-Z]` still search smart-case.
+fn has_literal_uppercase(pattern: &str) -> bool {
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => skip_escape(&mut chars),
+            '[' => {
+                // A `]` right after `[` or `[^` is a literal member
+                chars.next_if_eq(&'^');
+                chars.next_if_eq(&']');
+                let mut depth = 1;
+                while depth > 0 {
+                    match chars.next() {
+                        Some('\\') => skip_escape(&mut chars),
+                        Some('[') => depth += 1,
+                        Some(']') => depth -= 1,
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+            }
+            c if c.is_uppercase() => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Skip the rest of an escape whose backslash was just read, including the
+/// braces of `\p{..}` and `\x{..}`.
+fn skip_escape(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    if let Some('p' | 'P' | 'x' | 'u' | 'U') = chars.next() {
+        if chars.next_if_eq(&'{').is_some() {
+            for c in chars.by_ref() {
+                if c == '}' {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn search_tree(
+    root: &Path,
+    show_hidden: bool,
+    re: &Regex,
+    open: &HashMap<PathBuf, Rope>,
+    tx: &Sender<Vec<GrepMatch>>,
+    cancel: &AtomicBool,
+) {
+    for entry in WalkBuilder::new(root)
+        .hidden(!show_hidden)
+        .git_ignore(true)
+        .git_exclude(true)
+        .filter_entry(|e| e.file_name() != ".git")
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build()
+        .filter_map(|e| e.ok())
+    {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+            continue;
+        }
+        let Ok(relative) = entry.path().strip_prefix(root) else {
+            continue;
+        };
+        let matches = match open.get(relative) {
+            Some(text) => search_lines(relative, text::lines(text), re),
+            None => match read_text(entry.path()) {
+                Some(lines) => search_lines(relative, lines, re),
+                None => continue,
+            },
+        };
+        if !matches.is_empty() && tx.send(matches).is_err() {
+            return;
+        }
+    }
+}
+
+/// Decoded lines of `path`, or `None` if it is unreadable or binary.
+fn read_text(path: &Path) -> Option<Vec<String>> {
+    let bytes = fs::read(path).ok()?;
+    let utf16 = bytes.starts_with(&[0xFF, 0xFE]) || bytes.starts_with(&[0xFE, 0xFF]);
+    if !utf16 && bytes.iter().take(BINARY_PROBE).any(|&b| b == 0) {
+        return None;
+    }
+    file_format::decode(&bytes).ok().map(|(lines, _)| lines)
+}
+
+fn search_lines(path: &Path, lines: impl IntoIterator<Item = impl AsRef<str>>, re: &Regex) -> Vec<GrepMatch> {
+    let mut out = Vec::new();
+    for (line, text) in lines.into_iter().enumerate() {
+        let text = text.as_ref();
+        for m in re.find_iter(text) {
+            // Empty matches (e.g. `^`) would list every line
+            if m.is_empty() {
+                continue;
+            }
+            out.push(GrepMatch {
+                path: path.to_path_buf(),
+                line,
+                column: text[..m.start()].chars().count(),
+                text: text.to_string(),
+                range: m.range(),
+            });
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file holding `bytes`, removed again when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, bytes: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("teditor-grep-{}-{}", name, std::process::id()));
+            fs::write(&path, bytes).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn hits(query: &str, regex: bool, lines: &[&str]) -> Vec<(usize, usize, Range<usize>)> {
+        let re = build_regex(query, regex).unwrap();
+        search_lines(Path::new("f"), lines, &re)
+            .into_iter()
+            .map(|m| (m.line, m.column, m.range))
+            .collect()
+    }
+
+    #[test]
+    fn search_lines_reports_every_match_with_char_columns() {
+        assert_eq!(
+            hits("ab", false, &["ab ab", "none", "éab"]),
+            vec![(0, 0, 0..2), (0, 3, 3..5), (2, 1, 2..4)]
+        );
+    }
+
+    #[test]
+    fn search_lines_skips_empty_matches() {
+        assert_eq!(hits("^", true, &["one", "two"]), vec![]);
+        assert_eq!(hits("x*", true, &["axxb"]), vec![(0, 1, 1..3)]);
+    }
+
+    #[test]
+    fn literal_queries_escape_regex_syntax() {
+        assert_eq!(hits("a.c", false, &["abc a.c"]), vec![(0, 4, 4..7)]);
+    }
+
+    #[test]
+    fn lowercase_queries_ignore_case() {
+        assert_eq!(hits("todo", false, &["TODO Todo todo"]).len(), 3);
+    }
+
+    #[test]
+    fn uppercase_in_the_query_makes_it_case_sensitive() {
+        assert_eq!(hits("Todo", false, &["TODO Todo todo"]), vec![(0, 5, 5..9)]);
+    }
+
+    #[test]
+    fn regex_escapes_and_classes_keep_smart_case() {
+        for query in [r"\S+do", r"\Wtodo", r"\D", r"[A-Z]odo", r"\p{Lu}odo", r"[^\]A]odo"] {
+            assert!(build_regex(query, true).unwrap().is_match("a TODO"), "{}", query);
+        }
+    }
+
+    #[test]
+    fn uppercase_literals_in_a_regex_make_it_case_sensitive() {
+        assert_eq!(hits(r"\bTo\w+", true, &["TODO Todo todo"]), vec![(0, 5, 5..9)]);
+    }
+
+    #[test]
+    fn files_with_nul_bytes_are_skipped_as_binary() {
+        let file = TempFile::new("binary", b"match\0here");
+        assert_eq!(read_text(&file.0), None);
+    }
+
+    #[test]
+    fn utf16_files_are_not_taken_for_binary() {
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend("hi\nthere".encode_utf16().flat_map(u16::to_le_bytes));
+        let file = TempFile::new("utf16", &bytes);
+        assert_eq!(read_text(&file.0), Some(vec!["hi".to_string(), "there".to_string()]));
+    }
+
+    #[test]
+    fn text_files_are_split_into_lines() {
+        let file = TempFile::new("text", b"one\r\ntwo\r\n");
+        assert_eq!(read_text(&file.0), Some(vec!["one".to_string(), "two".to_string()]));
+    }
+}
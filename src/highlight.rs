@@ -0,0 +1,119 @@
+use ratatui::style::{Color, Style};
+use ropey::Rope;
+use std::iter;
+use syntect::highlighting::{
+    HighlightIterator, HighlightState, Highlighter, Style as SyntectStyle, Theme,
+};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+
+use crate::text;
+
+type State = (ParseState, HighlightState);
+
+#[derive(Clone)]
+struct CachedLine {
+    /// Parser and highlighter state going into the line
+    before: State,
+    /// ... and coming out of it
+    after: State,
+    spans: Vec<(Style, String)>,
+}
+
+/// Per-line syntax highlighting that survives edits.
+///
+/// Only lines up to the last one asked for are highlighted. After an edit,
+/// highlighting restarts at the first changed line; once a line is reached
+/// whose cached entry started from the same state, that entry and the ones
+/// after it are reused instead of re-highlighted.
+pub struct HighlightCache {
+    /// One slot per line. Entries at or past `valid` are left over from
+    /// before an edit and are only trusted once their `before` state checks
+    /// out.
+    lines: Vec<Option<Box<CachedLine>>>,
+    valid: usize,
+}
+
+impl HighlightCache {
+    pub fn new(line_count: usize) -> Self {
+        Self {
+            lines: iter::repeat_with(|| None).take(line_count).collect(),
+            valid: 0,
+        }
+    }
+
+    /// Note that `removed` lines starting at `start` were replaced by
+    /// `inserted` new ones. Cached lines before the change stay valid;
+    /// those after it are kept for reuse.
+    pub fn splice(&mut self, start: usize, removed: usize, inserted: usize) {
+        let start = start.min(self.lines.len());
+        let end = (start + removed).min(self.lines.len());
+        self.lines
+            .splice(start..end, iter::repeat_with(|| None).take(inserted));
+        self.valid = self.valid.min(start);
+    }
+
+    /// Make sure lines up to and including `target` are highlighted.
+    pub fn highlight_until(
+        &mut self,
+        text: &Rope,
+        target: usize,
+        syntax_set: &SyntaxSet,
+        syntax: &SyntaxReference,
+        theme: &Theme,
+    ) {
+        let end = (target + 1).min(self.lines.len());
+        if self.valid >= end {
+            return;
+        }
+
+        let highlighter = Highlighter::new(theme);
+        let mut state = match self.valid.checked_sub(1) {
+            Some(prev) => self.lines[prev].as_ref().map(|line| line.after.clone()),
+            None => None,
+        }
+        .unwrap_or_else(|| {
+            (
+                ParseState::new(syntax),
+                HighlightState::new(&highlighter, ScopeStack::new()),
+            )
+        });
+
+        while self.valid < end {
+            let idx = self.valid;
+            self.valid += 1;
+            // Converged: this line was highlighted from the same state before
+            if let Some(ref cached) = self.lines[idx] {
+                if cached.before == state {
+                    state = cached.after.clone();
+                    continue;
+                }
+            }
+
+            let before = state.clone();
+            let (ref mut parse, ref mut highlight) = state;
+            let line = format!("{}\n", text::line(text, idx));
+            let ops = parse.parse_line(&line, syntax_set).unwrap_or_default();
+            let spans = HighlightIterator::new(highlight, &ops, &line, &highlighter)
+                .map(|(style, piece)| (to_ratatui_style(style), piece.trim_end_matches('\n')))
+                .filter(|(_, piece)| !piece.is_empty())
+                .map(|(style, piece)| (style, piece.to_string()))
+                .collect();
+            self.lines[idx] = Some(Box::new(CachedLine {
+                before,
+                after: state.clone(),
+                spans,
+            }));
+        }
+    }
+
+    /// Styled spans for line `idx`, which may be stale if it was not
+    /// highlighted since the last edit. `None` if never highlighted.
+    pub fn spans(&self, idx: usize) -> Option<&[(Style, String)]> {
+        self.lines.get(idx)?.as_ref().map(|line| line.spans.as_slice())
+    }
+}
+
+fn to_ratatui_style(style: SyntectStyle) -> Style {
+    let fg = Color::Rgb(style.foreground.r, style.foreground.g, style.foreground.b);
+    Style::default().fg(fg)
+}
@@ -0,0 +1,532 @@
+use anyhow::{anyhow, bail, Result};
+use crossterm::event::{KeyCode, KeyModifiers};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::app::Mode;
+
+/// An operation keys can be bound to. Typing, cursor movement and the
+/// keys of popups and the find bar are not actions and stay fixed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Quit, asking first if buffers have unsaved changes
+    Quit,
+    /// Leave the editor or content search for the file list
+    Back,
+    ToggleHidden,
+    /// Show each file match's score, for tuning queries
+    ToggleScores,
+    NewFile,
+    BufferPicker,
+    ThemePicker,
+    SyntaxPicker,
+    Grep,
+    Find,
+    Undo,
+    Redo,
+    /// Delete from the cursor to the end of the line, or join the next
+    /// line when already there
+    DeleteToLineEnd,
+    Save,
+    /// Save under a new name, prompting for it
+    SaveAs,
+    NextBuffer,
+    PrevBuffer,
+    CloseBuffer,
+    SplitRight,
+    SplitDown,
+    ClosePane,
+    ShrinkPane,
+    GrowPane,
+    FocusLeft,
+    FocusRight,
+    FocusUp,
+    FocusDown,
+    Revert,
+    Merge,
+    CycleWrap,
+    ToggleLineEnding,
+    ToggleFinalNewline,
+    CycleEncoding,
+    ToggleReplace,
+    ToggleHit,
+    PreviewReplace,
+    ReplaceAll,
+    UndoReplace,
+    ToggleRegex,
+    ReloadConfig,
+    CommandPalette,
+}
+
+impl Action {
+    pub const ALL: &'static [Action] = &[
+        Action::Quit,
+        Action::Back,
+        Action::ToggleHidden,
+        Action::ToggleScores,
+        Action::NewFile,
+        Action::BufferPicker,
+        Action::ThemePicker,
+        Action::SyntaxPicker,
+        Action::Grep,
+        Action::Find,
+        Action::Undo,
+        Action::Redo,
+        Action::DeleteToLineEnd,
+        Action::Save,
+        Action::SaveAs,
+        Action::NextBuffer,
+        Action::PrevBuffer,
+        Action::CloseBuffer,
+        Action::SplitRight,
+        Action::SplitDown,
+        Action::ClosePane,
+        Action::ShrinkPane,
+        Action::GrowPane,
+        Action::FocusLeft,
+        Action::FocusRight,
+        Action::FocusUp,
+        Action::FocusDown,
+        Action::Revert,
+        Action::Merge,
+        Action::CycleWrap,
+        Action::ToggleLineEnding,
+        Action::ToggleFinalNewline,
+        Action::CycleEncoding,
+        Action::ToggleReplace,
+        Action::ToggleHit,
+        Action::PreviewReplace,
+        Action::ReplaceAll,
+        Action::UndoReplace,
+        Action::ToggleRegex,
+        Action::ReloadConfig,
+        Action::CommandPalette,
+    ];
+
+    /// Name used for the action in config files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Back => "back",
+            Action::ToggleHidden => "toggle_hidden",
+            Action::ToggleScores => "toggle_scores",
+            Action::NewFile => "new_file",
+            Action::BufferPicker => "buffer_picker",
+            Action::ThemePicker => "theme_picker",
+            Action::SyntaxPicker => "syntax_picker",
+            Action::Grep => "grep",
+            Action::Find => "find",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::DeleteToLineEnd => "delete_to_line_end",
+            Action::Save => "save",
+            Action::SaveAs => "save_as",
+            Action::NextBuffer => "next_buffer",
+            Action::PrevBuffer => "prev_buffer",
+            Action::CloseBuffer => "close_buffer",
+            Action::SplitRight => "split_right",
+            Action::SplitDown => "split_down",
+            Action::ClosePane => "close_pane",
+            Action::ShrinkPane => "shrink_pane",
+            Action::GrowPane => "grow_pane",
+            Action::FocusLeft => "focus_left",
+            Action::FocusRight => "focus_right",
+            Action::FocusUp => "focus_up",
+            Action::FocusDown => "focus_down",
+            Action::Revert => "revert",
+            Action::Merge => "merge",
+            Action::CycleWrap => "cycle_wrap",
+            Action::ToggleLineEnding => "toggle_line_ending",
+            Action::ToggleFinalNewline => "toggle_final_newline",
+            Action::CycleEncoding => "cycle_encoding",
+            Action::ToggleReplace => "toggle_replace",
+            Action::ToggleHit => "toggle_hit",
+            Action::PreviewReplace => "preview_replace",
+            Action::ReplaceAll => "replace_all",
+            Action::UndoReplace => "undo_replace",
+            Action::ToggleRegex => "toggle_regex",
+            Action::ReloadConfig => "reload_config",
+            Action::CommandPalette => "command_palette",
+        }
+    }
+
+    /// Name shown in the command palette.
+    pub fn title(self) -> &'static str {
+        match self {
+            Action::Quit => "Quit",
+            Action::Back => "Back to file search",
+            Action::ToggleHidden => "Toggle hidden files",
+            Action::ToggleScores => "Toggle match scores (debug)",
+            Action::NewFile => "New file or folder",
+            Action::BufferPicker => "Switch buffer",
+            Action::ThemePicker => "Change theme",
+            Action::SyntaxPicker => "Change syntax",
+            Action::Grep => "Search file contents",
+            Action::Find => "Find in file",
+            Action::Undo => "Undo",
+            Action::Redo => "Redo",
+            Action::DeleteToLineEnd => "Delete to end of line",
+            Action::Save => "Save",
+            Action::SaveAs => "Save as",
+            Action::NextBuffer => "Next buffer",
+            Action::PrevBuffer => "Previous buffer",
+            Action::CloseBuffer => "Close buffer",
+            Action::SplitRight => "Split pane right",
+            Action::SplitDown => "Split pane down",
+            Action::ClosePane => "Close pane",
+            Action::ShrinkPane => "Shrink pane",
+            Action::GrowPane => "Grow pane",
+            Action::FocusLeft => "Focus pane left",
+            Action::FocusRight => "Focus pane right",
+            Action::FocusUp => "Focus pane above",
+            Action::FocusDown => "Focus pane below",
+            Action::Revert => "Revert to file on disk",
+            Action::Merge => "Merge external changes",
+            Action::CycleWrap => "Cycle wrap mode",
+            Action::ToggleLineEnding => "Toggle line endings",
+            Action::ToggleFinalNewline => "Toggle final newline",
+            Action::CycleEncoding => "Cycle encoding",
+            Action::ToggleReplace => "Toggle replace mode",
+            Action::ToggleHit => "Include or exclude hit",
+            Action::PreviewReplace => "Preview replace",
+            Action::ReplaceAll => "Replace all",
+            Action::UndoReplace => "Undo last replace",
+            Action::ToggleRegex => "Toggle regex",
+            Action::ReloadConfig => "Reload config",
+            Action::CommandPalette => "Command palette",
+        }
+    }
+
+    /// Whether the action does anything in `mode`, which decides what
+    /// the command palette lists.
+    pub fn applies_to(self, mode: Mode) -> bool {
+        match self {
+            Action::Back => mode != Mode::Search,
+            Action::ToggleScores => mode == Mode::Search,
+            Action::SyntaxPicker
+            | Action::Find
+            | Action::Undo
+            | Action::Redo
+            | Action::DeleteToLineEnd
+            | Action::Save
+            | Action::SaveAs
+            | Action::NextBuffer
+            | Action::PrevBuffer
+            | Action::CloseBuffer
+            | Action::SplitRight
+            | Action::SplitDown
+            | Action::ClosePane
+            | Action::ShrinkPane
+            | Action::GrowPane
+            | Action::FocusLeft
+            | Action::FocusRight
+            | Action::FocusUp
+            | Action::FocusDown
+            | Action::Revert
+            | Action::Merge
+            | Action::CycleWrap
+            | Action::ToggleLineEnding
+            | Action::ToggleFinalNewline
+            | Action::CycleEncoding => mode == Mode::Edit,
+            Action::ToggleReplace
+            | Action::ToggleHit
+            | Action::PreviewReplace
+            | Action::ReplaceAll
+            | Action::UndoReplace
+            | Action::ToggleRegex => mode == Mode::Grep,
+            Action::Quit
+            | Action::ToggleHidden
+            | Action::NewFile
+            | Action::BufferPicker
+            | Action::ThemePicker
+            | Action::Grep
+            | Action::ReloadConfig
+            | Action::CommandPalette => true,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|action| action.name() == name)
+    }
+}
+
+/// A key with its modifiers, normalised so that a chord parsed from the
+/// config compares equal to the event the terminal sends for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyChord {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let mut modifiers = modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        let code = match code {
+            // The character already says whether Shift was held
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            KeyCode::BackTab => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::BackTab
+            }
+            code => code,
+        };
+        Self { code, modifiers }
+    }
+
+    /// Parse a chord like `ctrl+s`, `alt+shift+left`, `esc` or `f5`.
+    /// Letters mean the unshifted key whatever their case, as displayed, so
+    /// `Ctrl+S` is `ctrl+s` and a capital needs `shift+`.
+    pub fn parse(text: &str) -> Result<Self> {
+        // "+" itself can be the key, as in "ctrl++"
+        let (mods, key) = match text.strip_suffix("++") {
+            Some(mods) => (mods, "+"),
+            None => text.rsplit_once('+').unwrap_or(("", text)),
+        };
+        let mut modifiers = KeyModifiers::NONE;
+        for word in mods.split('+').filter(|word| !word.is_empty()) {
+            modifiers |= match word.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => bail!("unknown modifier `{}` in `{}`", word, text),
+            };
+        }
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c.to_ascii_lowercase()),
+            _ => named_key(&key.to_ascii_lowercase()).ok_or_else(|| anyhow!("unknown key `{}` in `{}`", key, text))?,
+        };
+        // Shift+Tab arrives as its own key
+        if code == KeyCode::Tab && modifiers.contains(KeyModifiers::SHIFT) {
+            return Ok(Self::new(KeyCode::BackTab, modifiers));
+        }
+        Ok(Self::new(code, modifiers))
+    }
+}
+
+impl fmt::Display for KeyChord {
+    /// As written in the docs: `Ctrl+S`, `Alt+Shift+Left`, `F5`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut shift = self.modifiers.contains(KeyModifiers::SHIFT);
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            f.write_str("Ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            f.write_str("Alt+")?;
+        }
+        let key = match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char(c) => {
+                // Lowercase letters are shown capitalised, so a capital
+                // one needs Shift
+                shift |= c.is_uppercase();
+                c.to_uppercase().to_string()
+            }
+            KeyCode::BackTab => {
+                shift = true;
+                "Tab".to_string()
+            }
+            KeyCode::F(n) => format!("F{}", n),
+            KeyCode::Esc => "Esc".to_string(),
+            KeyCode::PageUp => "PgUp".to_string(),
+            KeyCode::PageDown => "PgDn".to_string(),
+            code => format!("{:?}", code),
+        };
+        if shift {
+            f.write_str("Shift+")?;
+        }
+        f.write_str(&key)
+    }
+}
+
+fn named_key(name: &str) -> Option<KeyCode> {
+    let code = match name {
+        "esc" | "escape" => KeyCode::Esc,
+        "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" | "ins" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" | "pgup" => KeyCode::PageUp,
+        "pagedown" | "pgdn" => KeyCode::PageDown,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "space" => KeyCode::Char(' '),
+        _ => {
+            let number = name.strip_prefix('f')?.parse().ok()?;
+            return (1..=12).contains(&number).then_some(KeyCode::F(number));
+        }
+    };
+    Some(code)
+}
+
+/// Built-in bindings; the config can rebind or unbind each of them.
+const DEFAULTS: &[(Mode, &str, Action)] = &[
+    (Mode::Search, "esc", Action::Quit),
+    (Mode::Search, "ctrl+q", Action::Quit),
+    (Mode::Search, "tab", Action::ToggleHidden),
+    (Mode::Search, "ctrl+n", Action::NewFile),
+    (Mode::Search, "ctrl+b", Action::BufferPicker),
+    (Mode::Search, "ctrl+g", Action::Grep),
+    (Mode::Search, "ctrl+t", Action::ThemePicker),
+    (Mode::Search, "f5", Action::ReloadConfig),
+    (Mode::Search, "ctrl+p", Action::CommandPalette),
+    (Mode::Search, "ctrl+shift+p", Action::CommandPalette),
+    (Mode::Edit, "esc", Action::Back),
+    (Mode::Edit, "ctrl+q", Action::Quit),
+    (Mode::Edit, "ctrl+s", Action::Save),
+    (Mode::Edit, "ctrl+alt+s", Action::SaveAs),
+    (Mode::Edit, "ctrl+f", Action::Find),
+    (Mode::Edit, "ctrl+z", Action::Undo),
+    (Mode::Edit, "ctrl+y", Action::Redo),
+    (Mode::Edit, "ctrl+k", Action::DeleteToLineEnd),
+    (Mode::Edit, "alt+.", Action::NextBuffer),
+    (Mode::Edit, "alt+,", Action::PrevBuffer),
+    (Mode::Edit, "ctrl+b", Action::BufferPicker),
+    (Mode::Edit, "ctrl+w", Action::CloseBuffer),
+    (Mode::Edit, "ctrl+g", Action::Grep),
+    (Mode::Edit, "ctrl+t", Action::ThemePicker),
+    (Mode::Edit, "alt+s", Action::SyntaxPicker),
+    (Mode::Edit, "alt+\\", Action::SplitRight),
+    (Mode::Edit, "alt+-", Action::SplitDown),
+    (Mode::Edit, "alt+q", Action::ClosePane),
+    (Mode::Edit, "alt+[", Action::ShrinkPane),
+    (Mode::Edit, "alt+]", Action::GrowPane),
+    (Mode::Edit, "alt+shift+left", Action::FocusLeft),
+    (Mode::Edit, "alt+shift+right", Action::FocusRight),
+    (Mode::Edit, "alt+shift+up", Action::FocusUp),
+    (Mode::Edit, "alt+shift+down", Action::FocusDown),
+    (Mode::Edit, "ctrl+r", Action::Revert),
+    (Mode::Edit, "alt+m", Action::Merge),
+    (Mode::Edit, "alt+z", Action::CycleWrap),
+    (Mode::Edit, "alt+l", Action::ToggleLineEnding),
+    (Mode::Edit, "alt+t", Action::ToggleFinalNewline),
+    (Mode::Edit, "alt+e", Action::CycleEncoding),
+    (Mode::Edit, "f5", Action::ReloadConfig),
+    (Mode::Edit, "ctrl+p", Action::CommandPalette),
+    (Mode::Edit, "ctrl+shift+p", Action::CommandPalette),
+    (Mode::Grep, "esc", Action::Back),
+    (Mode::Grep, "ctrl+q", Action::Quit),
+    (Mode::Grep, "ctrl+b", Action::BufferPicker),
+    (Mode::Grep, "ctrl+r", Action::ToggleReplace),
+    (Mode::Grep, "ctrl+t", Action::ToggleHit),
+    (Mode::Grep, "ctrl+d", Action::PreviewReplace),
+    (Mode::Grep, "ctrl+a", Action::ReplaceAll),
+    (Mode::Grep, "ctrl+u", Action::UndoReplace),
+    (Mode::Grep, "alt+r", Action::ToggleRegex),
+    (Mode::Grep, "f5", Action::ReloadConfig),
+    (Mode::Grep, "ctrl+p", Action::CommandPalette),
+    (Mode::Grep, "ctrl+shift+p", Action::CommandPalette),
+];
+
+/// Which action each key runs in each mode.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: HashMap<(Mode, KeyChord), Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = DEFAULTS
+            .iter()
+            .map(|&(mode, key, action)| {
+                let chord = KeyChord::parse(key).expect("default bindings parse");
+                ((mode, chord), action)
+            })
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl Keymap {
+    pub fn get(&self, mode: Mode, code: KeyCode, modifiers: KeyModifiers) -> Option<Action> {
+        self.bindings.get(&(mode, KeyChord::new(code, modifiers))).copied()
+    }
+
+    /// Key to show for `action` in `mode`: the shortest bound one, so
+    /// `Ctrl+P` wins over `Ctrl+Shift+P`.
+    pub fn key_for(&self, mode: Mode, action: Action) -> Option<String> {
+        self.bindings
+            .iter()
+            .filter(|((bound_mode, _), bound)| *bound_mode == mode && **bound == action)
+            .map(|((_, chord), _)| chord.to_string())
+            .min_by_key(|key| (key.len(), key.clone()))
+    }
+
+    /// Key hints like `Ctrl+S: save | Esc: back` for the status bar of
+    /// `mode`, from the current bindings. The actions of an entry share its
+    /// label, with their keys joined by `/`; unbound ones are left out.
+    pub fn hints(&self, mode: Mode, entries: &[(&[Action], &str)]) -> String {
+        entries
+            .iter()
+            .filter_map(|(actions, label)| {
+                let keys: Vec<String> = actions
+                    .iter()
+                    .filter_map(|&action| self.key_for(mode, action))
+                    .collect();
+                (!keys.is_empty()).then(|| format!("{}: {}", keys.join("/"), label))
+            })
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    /// Bind `chord` in `mode`, or unbind it with `None`.
+    pub fn bind(&mut self, mode: Mode, chord: KeyChord, action: Option<Action>) {
+        match action {
+            Some(action) => self.bindings.insert((mode, chord), action),
+            None => self.bindings.remove(&(mode, chord)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(text: &str) -> KeyChord {
+        KeyChord::parse(text).unwrap()
+    }
+
+    #[test]
+    fn default_keys_survive_display_and_parse() {
+        for (_, key, _) in DEFAULTS {
+            let parsed = chord(key);
+            assert_eq!(chord(&parsed.to_string()), parsed, "{}", key);
+        }
+    }
+
+    #[test]
+    fn chords_display_as_documented() {
+        assert_eq!(chord("ctrl+s").to_string(), "Ctrl+S");
+        assert_eq!(chord("shift+s").to_string(), "Shift+S");
+        assert_eq!(chord("alt+shift+left").to_string(), "Alt+Shift+Left");
+        assert_eq!(chord("shift+tab").to_string(), "Shift+Tab");
+        assert_eq!(chord("ctrl++").to_string(), "Ctrl++");
+        assert_eq!(chord("space").to_string(), "Space");
+        assert_eq!(chord("pgdn").to_string(), "PgDn");
+        assert_eq!(chord("f5").to_string(), "F5");
+    }
+
+    #[test]
+    fn parsed_chords_match_terminal_events() {
+        assert_eq!(chord("Ctrl+S"), KeyChord::new(KeyCode::Char('s'), KeyModifiers::CONTROL));
+        assert_eq!(chord("shift+s"), KeyChord::new(KeyCode::Char('S'), KeyModifiers::SHIFT));
+        assert_eq!(chord("shift+tab"), KeyChord::new(KeyCode::BackTab, KeyModifiers::SHIFT));
+        assert_eq!(chord("control+meta+x"), chord("ctrl+alt+x"));
+    }
+
+    #[test]
+    fn bad_chords_are_rejected() {
+        for text in ["hyper+x", "ctrl+nope", "f13", "f0", "ctrl+", ""] {
+            assert!(KeyChord::parse(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use ratatui::layout::{Direction, Rect};
+
+use crate::viewport::Viewport;
+
+pub type PaneId = usize;
+
+/// A view onto a buffer. Several panes can show the same buffer, each with
+/// its own cursor.
+#[derive(Debug, Clone)]
+pub struct Pane {
+    /// `Buffer::id` of the buffer shown
+    pub buffer: usize,
+    /// Cursor to restore when this pane regains focus
+    pub cursor: (usize, usize),
+    pub viewport: Viewport,
+}
+
+/// Binary tree of panes. `Direction::Horizontal` places children side by
+/// side, `Direction::Vertical` stacks them.
+#[derive(Debug, Clone)]
+pub enum Node {
+    Leaf(PaneId),
+    Split {
+        direction: Direction,
+        /// Share of the space given to `first`, between 0.1 and 0.9
+        ratio: f32,
+        first: Box<Node>,
+        second: Box<Node>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Node {
+    /// Replace the leaf `target` with a split holding it and `new_pane`.
+    pub fn split(&mut self, target: PaneId, new_pane: PaneId, direction: Direction) -> bool {
+        match self {
+            Node::Leaf(id) if *id == target => {
+                *self = Node::Split {
+                    direction,
+                    ratio: 0.5,
+                    first: Box::new(Node::Leaf(target)),
+                    second: Box::new(Node::Leaf(new_pane)),
+                };
+                true
+            }
+            Node::Leaf(_) => false,
+            Node::Split { first, second, .. } => {
+                first.split(target, new_pane, direction) || second.split(target, new_pane, direction)
+            }
+        }
+    }
+
+    /// Remove the leaf `target`, letting its sibling take its space.
+    /// Returns the pane of the sibling that was next to `target`, to move
+    /// focus to.
+    pub fn remove(&mut self, target: PaneId) -> Option<PaneId> {
+        let Node::Split { first, second, .. } = self else {
+            return None;
+        };
+        if matches!(**first, Node::Leaf(id) if id == target) {
+            *self = std::mem::replace(second, Node::Leaf(target));
+            return self.leaves().first().copied();
+        }
+        if matches!(**second, Node::Leaf(id) if id == target) {
+            *self = std::mem::replace(first, Node::Leaf(target));
+            return self.leaves().last().copied();
+        }
+        first.remove(target).or_else(|| second.remove(target))
+    }
+
+    /// Grow (positive `delta`) or shrink the pane `target` within its
+    /// innermost enclosing split.
+    pub fn resize(&mut self, target: PaneId, delta: f32) -> bool {
+        let Node::Split {
+            ratio,
+            first,
+            second,
+            ..
+        } = self
+        else {
+            return false;
+        };
+        if first.contains(target) {
+            if first.resize(target, delta) {
+                return true;
+            }
+            *ratio = (*ratio + delta).clamp(0.1, 0.9);
+            return true;
+        }
+        if second.contains(target) {
+            if second.resize(target, delta) {
+                return true;
+            }
+            *ratio = (*ratio - delta).clamp(0.1, 0.9);
+            return true;
+        }
+        false
+    }
+
+    /// Whether `target` is somewhere in this subtree.
+    pub fn contains(&self, target: PaneId) -> bool {
+        match self {
+            Node::Leaf(id) => *id == target,
+            Node::Split { first, second, .. } => first.contains(target) || second.contains(target),
+        }
+    }
+
+    /// Pane ids in layout order (left to right, top to bottom).
+    pub fn leaves(&self) -> Vec<PaneId> {
+        self.areas(Rect::default()).into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// Screen rectangle of every pane when the tree fills `area`.
+    pub fn areas(&self, area: Rect) -> Vec<(PaneId, Rect)> {
+        let mut out = Vec::new();
+        self.collect_areas(area, &mut out);
+        out
+    }
+
+    fn collect_areas(&self, area: Rect, out: &mut Vec<(PaneId, Rect)>) {
+        match self {
+            Node::Leaf(id) => out.push((*id, area)),
+            Node::Split {
+                direction,
+                ratio,
+                first,
+                second,
+            } => {
+                let (a, b) = split_rect(area, *direction, *ratio);
+                first.collect_areas(a, out);
+                second.collect_areas(b, out);
+            }
+        }
+    }
+}
+
+fn split_rect(area: Rect, direction: Direction, ratio: f32) -> (Rect, Rect) {
+    match direction {
+        Direction::Horizontal => {
+            let w = ((area.width as f32) * ratio).round() as u16;
+            let w = w.clamp(1.min(area.width), area.width.saturating_sub(1).max(1));
+            (
+                Rect { width: w, ..area },
+                Rect {
+                    x: area.x + w,
+                    width: area.width.saturating_sub(w),
+                    ..area
+                },
+            )
+        }
+        Direction::Vertical => {
+            let h = ((area.height as f32) * ratio).round() as u16;
+            let h = h.clamp(1.min(area.height), area.height.saturating_sub(1).max(1));
+            (
+                Rect { height: h, ..area },
+                Rect {
+                    y: area.y + h,
+                    height: area.height.saturating_sub(h),
+                    ..area
+                },
+            )
+        }
+    }
+}
+
+/// The pane whose area lies next to `from` on `side`, preferring the one
+/// that overlaps it most.
+pub fn neighbour(areas: &[(PaneId, Rect)], from: PaneId, side: Side) -> Option<PaneId> {
+    let (_, cur) = areas.iter().find(|(id, _)| *id == from)?;
+    areas
+        .iter()
+        .filter(|(id, _)| *id != from)
+        .filter_map(|(id, r)| {
+            let adjacent = match side {
+                Side::Left => r.x + r.width == cur.x,
+                Side::Right => cur.x + cur.width == r.x,
+                Side::Up => r.y + r.height == cur.y,
+                Side::Down => cur.y + cur.height == r.y,
+            };
+            if !adjacent {
+                return None;
+            }
+            let overlap = match side {
+                Side::Left | Side::Right => {
+                    (cur.y + cur.height).min(r.y + r.height) as i32 - cur.y.max(r.y) as i32
+                }
+                Side::Up | Side::Down => {
+                    (cur.x + cur.width).min(r.x + r.width) as i32 - cur.x.max(r.x) as i32
+                }
+            };
+            (overlap > 0).then_some((*id, overlap))
+        })
+        .max_by_key(|&(_, overlap)| overlap)
+        .map(|(id, _)| id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 80,
+        height: 24,
+    };
+
+    /// Panes 0 and 2 stacked on the left, 1 and 3 on the right.
+    fn grid() -> Node {
+        let mut node = Node::Leaf(0);
+        assert!(node.split(0, 1, Direction::Horizontal));
+        assert!(node.split(0, 2, Direction::Vertical));
+        assert!(node.split(1, 3, Direction::Vertical));
+        node
+    }
+
+    fn area(node: &Node, pane: PaneId) -> (u16, u16, u16, u16) {
+        let (_, r) = node.areas(SCREEN).into_iter().find(|(id, _)| *id == pane).unwrap();
+        (r.x, r.y, r.width, r.height)
+    }
+
+    #[test]
+    fn split_puts_the_new_pane_after_its_target() {
+        let mut node = grid();
+        assert_eq!(node.leaves(), [0, 2, 1, 3]);
+        assert_eq!(area(&node, 0), (0, 0, 40, 12));
+        assert_eq!(area(&node, 2), (0, 12, 40, 12));
+        assert_eq!(area(&node, 1), (40, 0, 40, 12));
+        assert_eq!(area(&node, 3), (40, 12, 40, 12));
+        assert!(!node.split(9, 4, Direction::Vertical));
+    }
+
+    #[test]
+    fn removing_a_pane_hands_its_space_to_the_sibling() {
+        let mut node = grid();
+        assert_eq!(node.remove(3), Some(1));
+        assert_eq!(area(&node, 1), (40, 0, 40, 24));
+        assert_eq!(node.remove(0), Some(2));
+        assert_eq!(area(&node, 2), (0, 0, 40, 24));
+        assert_eq!(node.remove(9), None);
+        assert_eq!(node.remove(1), Some(2));
+        assert_eq!(node.remove(2), None);
+    }
+
+    #[test]
+    fn removing_next_to_a_split_focuses_the_nearest_pane_in_it() {
+        let beside = |grid_first: bool| Node::Split {
+            direction: Direction::Horizontal,
+            ratio: 0.5,
+            first: Box::new(if grid_first { grid() } else { Node::Leaf(7) }),
+            second: Box::new(if grid_first { Node::Leaf(7) } else { grid() }),
+        };
+        // Closing a pane left of the grid focuses its top left, one right of
+        // it its bottom right
+        assert_eq!(beside(false).remove(7), Some(0));
+        assert_eq!(beside(true).remove(7), Some(3));
+    }
+
+    #[test]
+    fn resize_moves_the_innermost_split() {
+        let mut node = grid();
+        assert!(node.resize(0, 0.1));
+        assert_eq!(area(&node, 0), (0, 0, 40, 14));
+        assert_eq!(area(&node, 1), (40, 0, 40, 12));
+        // Growing the second pane moves the split the other way
+        assert!(node.resize(3, 0.25));
+        assert_eq!(area(&node, 3), (40, 6, 40, 18));
+        // Clamped so neither side vanishes
+        assert!(node.resize(0, 5.0));
+        assert_eq!(area(&node, 2).3, 2);
+        assert!(!node.resize(9, 0.1));
+    }
+
+    #[test]
+    fn neighbour_finds_the_adjacent_pane_overlapping_most() {
+        let mut node = grid();
+        let areas = node.areas(SCREEN);
+        assert_eq!(neighbour(&areas, 3, Side::Left), Some(2));
+        assert_eq!(neighbour(&areas, 3, Side::Up), Some(1));
+        assert_eq!(neighbour(&areas, 0, Side::Right), Some(1));
+        assert_eq!(neighbour(&areas, 0, Side::Down), Some(2));
+        assert_eq!(neighbour(&areas, 0, Side::Up), None);
+        assert_eq!(neighbour(&areas, 0, Side::Left), None);
+
+        node.resize(0, 0.25);
+        node.remove(3);
+        let areas = node.areas(SCREEN);
+        assert_eq!(neighbour(&areas, 1, Side::Left), Some(0));
+    }
+}
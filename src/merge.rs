@@ -0,0 +1,306 @@
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+use std::ops::Range;
+
+/// Which version to keep for a conflicting hunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Choice {
+    Ours,
+    Theirs,
+    /// Ours followed by theirs
+    Both,
+    Base,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Neither side touched these lines.
+    Unchanged,
+    /// Only one side changed them, or both made the same change.
+    Auto,
+    /// Both sides changed them differently; `None` until the user picks.
+    Conflict(Option<Choice>),
+}
+
+/// A contiguous region of the base with each side's version of it.
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    pub base: Vec<String>,
+    pub ours: Vec<String>,
+    pub theirs: Vec<String>,
+    pub resolution: Resolution,
+}
+
+impl Hunk {
+    pub fn is_conflict(&self) -> bool {
+        matches!(self.resolution, Resolution::Conflict(_))
+    }
+
+    /// Lines this hunk contributes to the merge result, or `None` while an
+    /// unresolved conflict.
+    fn result(&self) -> Option<Vec<String>> {
+        match self.resolution {
+            Resolution::Unchanged => Some(self.base.clone()),
+            Resolution::Auto => {
+                if self.ours != self.base {
+                    Some(self.ours.clone())
+                } else {
+                    Some(self.theirs.clone())
+                }
+            }
+            Resolution::Conflict(None) => None,
+            Resolution::Conflict(Some(choice)) => Some(match choice {
+                Choice::Ours => self.ours.clone(),
+                Choice::Theirs => self.theirs.clone(),
+                Choice::Both => {
+                    let mut lines = self.ours.clone();
+                    lines.extend(self.theirs.iter().cloned());
+                    lines
+                }
+                Choice::Base => self.base.clone(),
+            }),
+        }
+    }
+}
+
+/// Three-way merge of two edited versions against their common base.
+pub struct Merge {
+    pub hunks: Vec<Hunk>,
+    /// The disk version the merge was computed against.
+    pub theirs: Vec<String>,
+}
+
+/// One side's change to the base: `base[range]` becomes `lines`.
+struct Change {
+    range: Range<usize>,
+    lines: Vec<String>,
+}
+
+impl Merge {
+    pub fn new(base: &[String], ours: &[String], theirs: &[String]) -> Self {
+        let our_changes = changes(base, ours);
+        let their_changes = changes(base, theirs);
+
+        let mut hunks = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        let mut pos = 0;
+
+        while i < our_changes.len() || j < their_changes.len() {
+            // Start a cluster at whichever change comes first
+            let first_start = match (our_changes.get(i), their_changes.get(j)) {
+                (Some(a), Some(b)) => a.range.start.min(b.range.start),
+                (Some(a), None) => a.range.start,
+                (None, Some(b)) => b.range.start,
+                (None, None) => unreachable!(),
+            };
+            let start = first_start;
+            let mut end = start;
+            let (oi, tj) = (i, j);
+
+            // Pull in every change from either side that overlaps the cluster
+            loop {
+                let mut grew = false;
+                for (changes, idx) in [(&our_changes, &mut i), (&their_changes, &mut j)] {
+                    while let Some(c) = changes.get(*idx) {
+                        let touches = c.range.start < end
+                            || (c.range.start == end && (c.range.is_empty() || start == end));
+                        if !touches {
+                            break;
+                        }
+                        end = end.max(c.range.end);
+                        *idx += 1;
+                        grew = true;
+                    }
+                }
+                if !grew {
+                    break;
+                }
+            }
+
+            if pos < start {
+                hunks.push(Hunk {
+                    base: base[pos..start].to_vec(),
+                    ours: base[pos..start].to_vec(),
+                    theirs: base[pos..start].to_vec(),
+                    resolution: Resolution::Unchanged,
+                });
+            }
+
+            let ours_side = &our_changes[oi..i];
+            let theirs_side = &their_changes[tj..j];
+            let ours_region = apply_changes(base, start..end, ours_side);
+            let theirs_region = apply_changes(base, start..end, theirs_side);
+            let resolution =
+                if ours_side.is_empty() || theirs_side.is_empty() || ours_region == theirs_region {
+                    Resolution::Auto
+                } else {
+                    Resolution::Conflict(None)
+                };
+            hunks.push(Hunk {
+                base: base[start..end].to_vec(),
+                ours: ours_region,
+                theirs: theirs_region,
+                resolution,
+            });
+            pos = end;
+        }
+
+        if pos < base.len() {
+            hunks.push(Hunk {
+                base: base[pos..].to_vec(),
+                ours: base[pos..].to_vec(),
+                theirs: base[pos..].to_vec(),
+                resolution: Resolution::Unchanged,
+            });
+        }
+
+        Self {
+            hunks,
+            theirs: theirs.to_vec(),
+        }
+    }
+
+    /// Indices of hunks that need a decision.
+    pub fn conflicts(&self) -> Vec<usize> {
+        self.hunks
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| h.is_conflict())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn unresolved(&self) -> usize {
+        self.hunks
+            .iter()
+            .filter(|h| h.resolution == Resolution::Conflict(None))
+            .count()
+    }
+
+    pub fn choose(&mut self, hunk: usize, choice: Choice) {
+        if let Some(h) = self.hunks.get_mut(hunk) {
+            if h.is_conflict() {
+                h.resolution = Resolution::Conflict(Some(choice));
+            }
+        }
+    }
+
+    /// The merged text, or `None` while conflicts remain unresolved.
+    pub fn result(&self) -> Option<Vec<String>> {
+        let mut lines = Vec::new();
+        for hunk in &self.hunks {
+            lines.extend(hunk.result()?);
+        }
+        Some(lines)
+    }
+}
+
+fn changes(base: &[String], other: &[String]) -> Vec<Change> {
+    let mut out: Vec<Change> = Vec::new();
+    for op in capture_diff_slices(Algorithm::Myers, base, other) {
+        let (range, lines) = match op {
+            DiffOp::Equal { .. } => continue,
+            DiffOp::Delete {
+                old_index, old_len, ..
+            } => (old_index..old_index + old_len, Vec::new()),
+            DiffOp::Insert {
+                old_index,
+                new_index,
+                new_len,
+            } => (old_index..old_index, other[new_index..new_index + new_len].to_vec()),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => (
+                old_index..old_index + old_len,
+                other[new_index..new_index + new_len].to_vec(),
+            ),
+        };
+        // Adjacent delete + insert from the differ form one change
+        if let Some(last) = out.last_mut() {
+            if last.range.end == range.start {
+                last.range.end = range.end;
+                last.lines.extend(lines);
+                continue;
+            }
+        }
+        out.push(Change { range, lines });
+    }
+    out
+}
+
+/// `base[range]` with the given changes (all inside `range`) applied.
+fn apply_changes(base: &[String], range: Range<usize>, changes: &[Change]) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut pos = range.start;
+    for change in changes {
+        out.extend_from_slice(&base[pos..change.range.start]);
+        out.extend(change.lines.iter().cloned());
+        pos = change.range.end;
+    }
+    out.extend_from_slice(&base[pos..range.end]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> Merge {
+        Merge::new(&lines(base), &lines(ours), &lines(theirs))
+    }
+
+    #[test]
+    fn disjoint_changes_merge_cleanly() {
+        let merge = merge("a b c d e", "a B c d e", "a b c D e");
+        assert!(merge.conflicts().is_empty());
+        assert_eq!(merge.result(), Some(lines("a B c D e")));
+    }
+
+    #[test]
+    fn changes_to_adjacent_lines_merge_cleanly() {
+        let merge = merge("a b c d", "a B c d", "a b C d");
+        assert!(merge.conflicts().is_empty());
+        assert_eq!(merge.result(), Some(lines("a B C d")));
+    }
+
+    #[test]
+    fn overlapping_changes_conflict() {
+        let mut merge = merge("a b c d", "a B1 C1 d", "a b C2 d");
+        assert_eq!(merge.conflicts().len(), 1);
+        assert_eq!(merge.unresolved(), 1);
+        assert_eq!(merge.result(), None);
+
+        let conflict = merge.conflicts()[0];
+        assert_eq!(merge.hunks[conflict].base, lines("b c"));
+        merge.choose(conflict, Choice::Theirs);
+        assert_eq!(merge.unresolved(), 0);
+        assert_eq!(merge.result(), Some(lines("a b C2 d")));
+        merge.choose(conflict, Choice::Ours);
+        assert_eq!(merge.result(), Some(lines("a B1 C1 d")));
+    }
+
+    #[test]
+    fn identical_changes_on_both_sides_merge_cleanly() {
+        let merge = merge("a b c", "a B c x", "a B c x");
+        assert!(merge.conflicts().is_empty());
+        assert_eq!(merge.result(), Some(lines("a B c x")));
+    }
+
+    #[test]
+    fn insertions_at_the_same_point_conflict() {
+        let mut merge = merge("a b", "a x b", "a y b");
+        assert_eq!(merge.conflicts().len(), 1);
+        let conflict = merge.conflicts()[0];
+        assert!(merge.hunks[conflict].base.is_empty());
+        merge.choose(conflict, Choice::Both);
+        assert_eq!(merge.result(), Some(lines("a x y b")));
+        merge.choose(conflict, Choice::Base);
+        assert_eq!(merge.result(), Some(lines("a b")));
+    }
+}
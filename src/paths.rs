@@ -0,0 +1,51 @@
+use std::path::{Path, PathBuf};
+
+/// Directory for persistent editor state (undo history, swap files).
+///
+/// `$TEDITOR_STATE_DIR` wins if set. Otherwise follows the XDG base
+/// directory spec: `$XDG_STATE_HOME/teditor`, falling back to
+/// `~/.local/state/teditor`.
+pub fn state_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("TEDITOR_STATE_DIR").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = std::env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("teditor"));
+    }
+    std::env::var_os("HOME")
+        .filter(|d| !d.is_empty())
+        .map(|home| PathBuf::from(home).join(".local/state/teditor"))
+}
+
+/// Directory for user configuration such as extra themes.
+///
+/// `$TEDITOR_CONFIG_DIR` wins if set. Otherwise `$XDG_CONFIG_HOME/teditor`,
+/// falling back to `~/.config/teditor`.
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("TEDITOR_CONFIG_DIR").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("teditor"));
+    }
+    std::env::var_os("HOME")
+        .filter(|d| !d.is_empty())
+        .map(|home| PathBuf::from(home).join(".config/teditor"))
+}
+
+/// Directory for data that can be rebuilt, like compiled syntaxes:
+/// `$XDG_CACHE_HOME/teditor`, falling back to `~/.cache/teditor`.
+pub fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("teditor"));
+    }
+    std::env::var_os("HOME")
+        .filter(|d| !d.is_empty())
+        .map(|home| PathBuf::from(home).join(".cache/teditor"))
+}
+
+/// Absolute, symlink-resolved form of `path` used to key per-file state.
+/// Falls back to the path as given when it cannot be resolved.
+pub fn state_key(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
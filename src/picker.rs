@@ -0,0 +1,57 @@
+use crate::search::FileSearch;
+
+/// What a picker's selection is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickerKind {
+    Buffer,
+    /// Previews the highlighted theme as the selection moves
+    Theme,
+    /// Syntax for the active buffer
+    Syntax,
+    /// Every action that applies in the mode it was opened from
+    Command,
+}
+
+/// A fuzzy-filtered list shown as a popup.
+pub struct Picker {
+    pub kind: PickerKind,
+    pub title: String,
+    pub query: String,
+    pub items: Vec<String>,
+    /// Dimmed text shown at the right of each item, such as its key
+    pub hints: Vec<String>,
+    /// Indices into `items` that match `query`, best first
+    pub matches: Vec<usize>,
+    pub selected: usize,
+}
+
+impl Picker {
+    pub fn new(kind: PickerKind, title: impl Into<String>, items: Vec<String>) -> Self {
+        let matches = (0..items.len()).collect();
+        Self {
+            kind,
+            title: title.into(),
+            query: String::new(),
+            items,
+            hints: Vec::new(),
+            matches,
+            selected: 0,
+        }
+    }
+
+    /// Re-filter `items` against `query` using the file finder's matcher.
+    pub fn update(&mut self, search: &mut FileSearch) {
+        self.matches = search.rank(&self.query, &self.items);
+        self.selected = 0;
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        let max = self.matches.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(max);
+    }
+
+    /// Index into `items` of the highlighted entry.
+    pub fn selected_item(&self) -> Option<usize> {
+        self.matches.get(self.selected).copied()
+    }
+}
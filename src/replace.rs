@@ -0,0 +1,146 @@
+use regex::Regex;
+use std::path::PathBuf;
+
+use crate::grep::GrepMatch;
+
+/// What a project-wide replace changed, kept so it can be reverted as one
+/// step.
+#[derive(Default)]
+pub struct ReplaceUndo {
+    /// Files rewritten on disk: path, bytes before, bytes written
+    pub files: Vec<(PathBuf, Vec<u8>, Vec<u8>)>,
+    /// Open buffers edited in place: buffer id and the revision left in it
+    pub buffers: Vec<(usize, u64)>,
+}
+
+/// Text that replaces the match of `re` starting at byte `start` of
+/// `text`, with `$1`-style groups expanded when `expand` is set. `None`
+/// if the pattern no longer matches there.
+pub fn substitute(
+    re: &Regex,
+    text: &str,
+    start: usize,
+    replacement: &str,
+    expand: bool,
+) -> Option<(usize, String)> {
+    let caps = re.captures_at(text, start)?;
+    let m = caps.get(0)?;
+    if m.start() != start || m.is_empty() {
+        return None;
+    }
+    let mut out = String::new();
+    if expand {
+        caps.expand(replacement, &mut out);
+    } else {
+        out.push_str(replacement);
+    }
+    Some((m.end(), out))
+}
+
+/// Apply `hits` to `lines`. Hits whose line no longer reads as it did
+/// when searched are skipped. Returns the new lines and how many hits
+/// were applied and skipped.
+pub fn replace_in_lines(
+    lines: &[String],
+    hits: &[&GrepMatch],
+    re: &Regex,
+    replacement: &str,
+    expand: bool,
+) -> (Vec<String>, usize, usize) {
+    let mut out = lines.to_vec();
+    let (mut applied, mut stale) = (0, 0);
+
+    let mut hits = hits.to_vec();
+    hits.sort_by_key(|h| (h.line, h.range.start));
+    for line_hits in hits.chunk_by(|a, b| a.line == b.line) {
+        let line = line_hits[0].line;
+        let Some(text) = lines.get(line).filter(|t| **t == line_hits[0].text) else {
+            stale += line_hits.len();
+            continue;
+        };
+        let mut new_text = String::with_capacity(text.len());
+        let mut pos = 0;
+        for hit in line_hits {
+            match substitute(re, text, hit.range.start, replacement, expand) {
+                Some((end, with)) if hit.range.start >= pos => {
+                    new_text.push_str(&text[pos..hit.range.start]);
+                    new_text.push_str(&with);
+                    pos = end;
+                    applied += 1;
+                }
+                _ => stale += 1,
+            }
+        }
+        new_text.push_str(&text[pos..]);
+        out[line] = new_text;
+    }
+    (out, applied, stale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    /// Every match of `re` in `lines`, as a search would report it.
+    fn hits(lines: &[String], re: &Regex) -> Vec<GrepMatch> {
+        let mut out = Vec::new();
+        for (line, text) in lines.iter().enumerate() {
+            for m in re.find_iter(text) {
+                out.push(GrepMatch {
+                    path: PathBuf::from("file.rs"),
+                    line,
+                    column: text[..m.start()].chars().count(),
+                    text: text.clone(),
+                    range: m.range(),
+                });
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn expands_groups_for_every_hit() {
+        let before = lines(&["let a = foo(1) + foo(2);", "bar", "foo(3)"]);
+        let re = Regex::new(r"foo\((\d)\)").unwrap();
+        let found = hits(&before, &re);
+        let all: Vec<&GrepMatch> = found.iter().collect();
+        let (after, applied, stale) = replace_in_lines(&before, &all, &re, "bar($1)", true);
+        assert_eq!(after, lines(&["let a = bar(1) + bar(2);", "bar", "bar(3)"]));
+        assert_eq!((applied, stale), (3, 0));
+    }
+
+    #[test]
+    fn literal_replacements_keep_dollars() {
+        let before = lines(&["x = 1"]);
+        let re = Regex::new(r"(x)").unwrap();
+        let found = hits(&before, &re);
+        let (after, _, _) = replace_in_lines(&before, &[&found[0]], &re, "$1y", false);
+        assert_eq!(after, lines(&["$1y = 1"]));
+    }
+
+    #[test]
+    fn only_given_hits_are_replaced() {
+        let before = lines(&["a a a"]);
+        let re = Regex::new("a").unwrap();
+        let found = hits(&before, &re);
+        let (after, applied, _) = replace_in_lines(&before, &[&found[2], &found[0]], &re, "b", true);
+        assert_eq!(after, lines(&["b a b"]));
+        assert_eq!(applied, 2);
+    }
+
+    #[test]
+    fn lines_changed_since_the_search_are_skipped() {
+        let searched = lines(&["foo", "foo foo"]);
+        let re = Regex::new("foo").unwrap();
+        let found = hits(&searched, &re);
+        let all: Vec<&GrepMatch> = found.iter().collect();
+        let now = lines(&["foo", "foo bar"]);
+        let (after, applied, stale) = replace_in_lines(&now, &all, &re, "baz", true);
+        assert_eq!(after, lines(&["baz", "foo bar"]));
+        assert_eq!((applied, stale), (1, 2));
+    }
+}
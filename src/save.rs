@@ -0,0 +1,461 @@
+use serde::Deserialize;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Whether to keep a copy of the previous file contents when saving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupMode {
+    #[default]
+    None,
+    /// `file~`, as Emacs and Vim do
+    Tilde,
+    /// `file.bak`
+    Bak,
+}
+
+impl BackupMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" | "off" | "" => Some(BackupMode::None),
+            "tilde" | "~" => Some(BackupMode::Tilde),
+            "bak" | ".bak" => Some(BackupMode::Bak),
+            _ => None,
+        }
+    }
+
+    /// `TEDITOR_BACKUP` (`none`, `tilde` or `bak`) if set; it overrides
+    /// the config.
+    pub fn from_env() -> Option<Self> {
+        std::env::var("TEDITOR_BACKUP")
+            .ok()
+            .and_then(|v| Self::parse(v.trim()))
+    }
+
+    fn backup_path(self, target: &Path) -> Option<PathBuf> {
+        let name = target.file_name()?.to_string_lossy().to_string();
+        let backup = match self {
+            BackupMode::None => return None,
+            BackupMode::Tilde => format!("{}~", name),
+            BackupMode::Bak => format!("{}.bak", name),
+        };
+        Some(target.with_file_name(backup))
+    }
+}
+
+/// Why a save failed. Every variant leaves the original file untouched
+/// except `Write` during an in-place fallback (see `write_file`).
+#[derive(Debug)]
+pub enum SaveError {
+    /// The buffer cannot be represented in the file's encoding.
+    Encode(String),
+    /// The file mixes CRLF and LF, and writing it would convert lines
+    /// nobody touched; one style has to be picked first.
+    MixedLineEndings,
+    /// A symlink in the path could not be resolved.
+    ResolveTarget { path: PathBuf, source: io::Error },
+    Backup { path: PathBuf, source: io::Error },
+    CreateTemp { dir: PathBuf, source: io::Error },
+    Write { path: PathBuf, source: io::Error },
+    Sync { path: PathBuf, source: io::Error },
+    Rename { path: PathBuf, source: io::Error },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Encode(msg) => write!(f, "cannot encode buffer: {}", msg),
+            SaveError::MixedLineEndings => {
+                write!(f, "mixed line endings; toggle line endings to pick LF or CRLF first")
+            }
+            SaveError::ResolveTarget { path, source } => {
+                write!(f, "cannot resolve {}: {}", path.display(), describe(source))
+            }
+            SaveError::Backup { path, source } => {
+                write!(f, "backup {} failed: {}", path.display(), describe(source))
+            }
+            SaveError::CreateTemp { dir, source } => write!(
+                f,
+                "cannot create temp file in {}: {}",
+                dir.display(),
+                describe(source)
+            ),
+            SaveError::Write { path, source } => {
+                write!(f, "writing {} failed: {}", path.display(), describe(source))
+            }
+            SaveError::Sync { path, source } => {
+                write!(f, "flushing {} failed: {}", path.display(), describe(source))
+            }
+            SaveError::Rename { path, source } => {
+                write!(f, "replacing {} failed: {}", path.display(), describe(source))
+            }
+        }
+    }
+}
+
+impl std::error::Error for SaveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SaveError::Encode(_) | SaveError::MixedLineEndings => None,
+            SaveError::ResolveTarget { source, .. }
+            | SaveError::Backup { source, .. }
+            | SaveError::CreateTemp { source, .. }
+            | SaveError::Write { source, .. }
+            | SaveError::Sync { source, .. }
+            | SaveError::Rename { source, .. } => Some(source),
+        }
+    }
+}
+
+fn describe(err: &io::Error) -> String {
+    match err.kind() {
+        io::ErrorKind::StorageFull => "disk full".to_string(),
+        io::ErrorKind::PermissionDenied => "permission denied".to_string(),
+        io::ErrorKind::ReadOnlyFilesystem => "read-only file system".to_string(),
+        _ => err.to_string(),
+    }
+}
+
+/// Write `bytes` to `path` without ever leaving a truncated file behind.
+///
+/// Symlinks are followed so the link itself survives and the file it points
+/// at is replaced. The data goes to a temp file in the target's directory,
+/// is fsynced, given the original mode and owner, then renamed over the
+/// target. If the owner cannot be restored or the file has other hard
+/// links, renaming would change who owns the file or split the links, so
+/// the file is rewritten in place instead.
+pub fn write_file(path: &Path, bytes: &[u8], backup: BackupMode) -> Result<(), SaveError> {
+    write_with(path, backup, |out| out.write_all(bytes))
+}
+
+/// `write_file` for contents produced piece by piece, so they never have
+/// to be held whole. `write` fills the file it is given and may be called
+/// a second time if the in-place fallback kicks in after a temp file.
+pub fn write_with(
+    path: &Path,
+    backup: BackupMode,
+    write: impl Fn(&mut dyn Write) -> io::Result<()>,
+) -> Result<(), SaveError> {
+    let target = resolve_target(path)?;
+    let existing = fs::metadata(&target).ok();
+
+    if let (Some(backup_path), true) = (backup.backup_path(&target), existing.is_some()) {
+        fs::copy(&target, &backup_path).map_err(|source| SaveError::Backup {
+            path: backup_path.clone(),
+            source,
+        })?;
+    }
+
+    if existing.as_ref().is_some_and(has_other_links) {
+        return write_in_place(&target, &write);
+    }
+
+    let dir = match target.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let (tmp_path, tmp) = create_temp(&dir, &target)?;
+    let result = (|| {
+        fill(&tmp, &write).map_err(|source| SaveError::Write {
+            path: tmp_path.clone(),
+            source,
+        })?;
+        if let Some(meta) = &existing {
+            fs::set_permissions(&tmp_path, meta.permissions()).map_err(|source| {
+                SaveError::Write {
+                    path: tmp_path.clone(),
+                    source,
+                }
+            })?;
+            if !copy_owner(meta, &tmp_path) {
+                return Ok(false);
+            }
+        }
+        tmp.sync_all().map_err(|source| SaveError::Sync {
+            path: tmp_path.clone(),
+            source,
+        })?;
+        fs::rename(&tmp_path, &target).map_err(|source| SaveError::Rename {
+            path: target.clone(),
+            source,
+        })?;
+        Ok(true)
+    })();
+
+    match result {
+        Ok(true) => {
+            sync_dir(&dir);
+            Ok(())
+        }
+        Ok(false) => {
+            let _ = fs::remove_file(&tmp_path);
+            write_in_place(&target, &write)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+/// Follow symlinks to the file that should actually be written.
+fn resolve_target(path: &Path) -> Result<PathBuf, SaveError> {
+    let is_link = fs::symlink_metadata(path)
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+    if !is_link {
+        return Ok(path.to_path_buf());
+    }
+    match fs::canonicalize(path) {
+        Ok(real) => Ok(real),
+        // Dangling link: create the file it points at
+        Err(_) => {
+            let link = fs::read_link(path).map_err(|source| SaveError::ResolveTarget {
+                path: path.to_path_buf(),
+                source,
+            })?;
+            Ok(match path.parent() {
+                Some(parent) => parent.join(link),
+                None => link,
+            })
+        }
+    }
+}
+
+fn create_temp(dir: &Path, target: &Path) -> Result<(PathBuf, File), SaveError> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    loop {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tmp_path = dir.join(format!(".{}.{}.{}.tmp", name, std::process::id(), n));
+        match OpenOptions::new().write(true).create_new(true).open(&tmp_path) {
+            Ok(file) => return Ok((tmp_path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(source) => {
+                return Err(SaveError::CreateTemp {
+                    dir: dir.to_path_buf(),
+                    source,
+                })
+            }
+        }
+    }
+}
+
+fn write_in_place(target: &Path, write: &impl Fn(&mut dyn Write) -> io::Result<()>) -> Result<(), SaveError> {
+    let write_err = |source| SaveError::Write {
+        path: target.to_path_buf(),
+        source,
+    };
+    let file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(target)
+        .map_err(write_err)?;
+    fill(&file, write).map_err(write_err)?;
+    file.sync_all().map_err(|source| SaveError::Sync {
+        path: target.to_path_buf(),
+        source,
+    })
+}
+
+/// Run `write` against `file` through a buffer.
+fn fill(file: &File, write: &impl Fn(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+    let mut out = BufWriter::new(file);
+    write(&mut out)?;
+    out.flush()
+}
+
+#[cfg(unix)]
+fn has_other_links(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    meta.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn has_other_links(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+/// Give `path` the owner and group from `meta`. Returns false if that
+/// is not permitted.
+#[cfg(unix)]
+fn copy_owner(meta: &fs::Metadata, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let current = match fs::metadata(path) {
+        Ok(m) => m,
+        Err(_) => return false,
+    };
+    if current.uid() == meta.uid() && current.gid() == meta.gid() {
+        return true;
+    }
+    std::os::unix::fs::chown(path, Some(meta.uid()), Some(meta.gid())).is_ok()
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_meta: &fs::Metadata, _path: &Path) -> bool {
+    true
+}
+
+/// Make the rename durable. Best effort: not every platform can open a
+/// directory for syncing.
+fn sync_dir(dir: &Path) {
+    #[cfg(unix)]
+    if let Ok(d) = File::open(dir) {
+        let _ = d.sync_all();
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory, removed again when dropped.
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("teditor-save-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn file(&self, name: &str, text: &str) -> PathBuf {
+            let path = self.0.join(name);
+            fs::write(&path, text).unwrap();
+            path
+        }
+
+        fn names(&self) -> Vec<String> {
+            let mut names: Vec<String> = fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn new_files_are_created() {
+        let dir = Dir::new("new");
+        write_file(&dir.0.join("a.txt"), b"hello", BackupMode::Tilde).unwrap();
+        assert_eq!(fs::read_to_string(dir.0.join("a.txt")).unwrap(), "hello");
+        // Nothing to back up yet
+        assert_eq!(dir.names(), vec!["a.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saves_rename_a_new_file_over_the_old_one() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let dir = Dir::new("atomic");
+        let path = dir.file("a.txt", "old");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let before = fs::metadata(&path).unwrap().ino();
+
+        write_file(&path, b"new", BackupMode::None).unwrap();
+
+        let meta = fs::metadata(&path).unwrap();
+        assert_ne!(meta.ino(), before);
+        assert_eq!(meta.mode() & 0o777, 0o640);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        // The temp file is gone
+        assert_eq!(dir.names(), vec!["a.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saves_through_a_symlink_replace_its_target() {
+        let dir = Dir::new("symlink");
+        let real = dir.file("real.txt", "old");
+        let link = dir.0.join("link.txt");
+        std::os::unix::fs::symlink("real.txt", &link).unwrap();
+
+        write_file(&link, b"new", BackupMode::None).unwrap();
+
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&real).unwrap(), "new");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saves_through_a_dangling_symlink_create_its_target() {
+        let dir = Dir::new("dangling");
+        let link = dir.0.join("link.txt");
+        std::os::unix::fs::symlink("real.txt", &link).unwrap();
+
+        write_file(&link, b"new", BackupMode::None).unwrap();
+
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(dir.0.join("real.txt")).unwrap(), "new");
+    }
+
+    #[test]
+    fn backups_keep_the_previous_contents_next_to_the_file() {
+        let dir = Dir::new("backup");
+        let tilde = dir.file("a.txt", "old a");
+        let bak = dir.file("b.txt", "old b");
+
+        write_file(&tilde, b"new a", BackupMode::Tilde).unwrap();
+        write_file(&bak, b"new b", BackupMode::Bak).unwrap();
+
+        assert_eq!(dir.names(), vec!["a.txt", "a.txt~", "b.txt", "b.txt.bak"]);
+        assert_eq!(fs::read_to_string(dir.0.join("a.txt~")).unwrap(), "old a");
+        assert_eq!(fs::read_to_string(dir.0.join("b.txt.bak")).unwrap(), "old b");
+        assert_eq!(fs::read_to_string(&tilde).unwrap(), "new a");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn backups_of_a_symlink_are_named_after_its_target() {
+        let dir = Dir::new("backup-link");
+        dir.file("real.txt", "old");
+        let link = dir.0.join("link.txt");
+        std::os::unix::fs::symlink("real.txt", &link).unwrap();
+
+        write_file(&link, b"new", BackupMode::Bak).unwrap();
+
+        assert_eq!(dir.names(), vec!["link.txt", "real.txt", "real.txt.bak"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_linked_files_are_rewritten_in_place() {
+        use std::os::unix::fs::MetadataExt;
+        let dir = Dir::new("hardlink");
+        let path = dir.file("a.txt", "old text");
+        let other = dir.0.join("b.txt");
+        fs::hard_link(&path, &other).unwrap();
+        let before = fs::metadata(&path).unwrap().ino();
+
+        write_file(&path, b"new", BackupMode::None).unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().ino(), before);
+        // Truncated, and seen through the other link
+        assert_eq!(fs::read_to_string(&other).unwrap(), "new");
+        assert_eq!(dir.names(), vec!["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn backup_modes_parse_their_aliases() {
+        assert_eq!(BackupMode::parse("~"), Some(BackupMode::Tilde));
+        assert_eq!(BackupMode::parse(".bak"), Some(BackupMode::Bak));
+        assert_eq!(BackupMode::parse("off"), Some(BackupMode::None));
+        assert_eq!(BackupMode::parse("yes"), None);
+    }
+}
@@ -1,8 +1,13 @@
 use anyhow::Result;
 use ignore::WalkBuilder;
-use nucleo::{Config, Matcher, Utf32Str};
+use nucleo::pattern::{CaseMatching, Normalization};
+use nucleo::{Config, Injector, Matcher, Nucleo, Utf32Str};
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
 
 #[derive(Debug, Clone)]
 pub struct Entry {
@@ -18,66 +23,63 @@ struct TreeNode {
     children_loaded: bool,
 }
 
+/// A file matching the query.
+pub struct PathMatch<'a> {
+    pub path: &'a PathBuf,
+    pub score: u32,
+    /// Grapheme clusters of the path the query matched, in ascending order
+    pub indices: Vec<u32>,
+}
+
 pub struct FileSearch {
     pub root: PathBuf,
-    files: Vec<PathBuf>,           // All files (flat, for search)
+    /// All files (flat, for search), matched on nucleo's worker threads
+    files: Nucleo<PathBuf>,
+    /// Query the pattern was last parsed from
+    query: String,
     expanded: HashSet<PathBuf>,    // Expanded directories
-    matches: Vec<(usize, u32)>,    // Search matches (index into files, score)
     matcher: Matcher,
+    /// Scores paths the way `files` does, for showing them
+    path_matcher: RefCell<Matcher>,
     pub search_active: bool,
     pub show_hidden: bool,         // Whether to show hidden files (default: true)
     pub indexing: bool,
+    /// Stops the walk feeding `files` when a newer one replaces it
+    walk_cancel: Arc<AtomicBool>,
+    walk_done: Arc<AtomicBool>,
     tree_nodes: HashMap<PathBuf, TreeNode>,
     tree_visible: Vec<Entry>,
 }
 
 impl FileSearch {
-    #[allow(dead_code)]
-    pub fn new(root: PathBuf) -> Result<Self> {
-        let show_hidden = true; // Default: show hidden files
-        let files = Self::collect_files(&root, show_hidden)?;
-        let matches: Vec<(usize, u32)> = files.iter().enumerate().map(|(i, _)| (i, 0)).collect();
+    /// Start with the tree's first level loaded and the file index being
+    /// built in the background; matches come in as the walk finds files.
+    pub fn new_deferred(root: PathBuf, show_hidden: bool) -> Result<Self> {
 
         // Start with root-level directories expanded
         let mut expanded = HashSet::new();
         expanded.insert(PathBuf::new()); // Root is always "expanded"
 
+        let config = Config::DEFAULT.match_paths();
+        // The run loop ticks the matcher anyway, so it needs no wake-up
+        let files = Nucleo::new(config.clone(), Arc::new(|| {}), None, 1);
         let mut search = Self {
             root,
             files,
+            query: String::new(),
             expanded,
-            matches,
-            matcher: Matcher::new(Config::DEFAULT),
-            search_active: false,
-            show_hidden,
-            indexing: false,
-            tree_nodes: HashMap::new(),
-            tree_visible: Vec::new(),
-        };
-        search.init_tree_root()?;
-        Ok(search)
-    }
-
-    pub fn new_deferred(root: PathBuf) -> Result<Self> {
-        let show_hidden = true; // Default: show hidden files
-
-        // Start with root-level directories expanded
-        let mut expanded = HashSet::new();
-        expanded.insert(PathBuf::new()); // Root is always "expanded"
-
-        let mut search = Self {
-            root,
-            files: Vec::new(),
-            expanded,
-            matches: Vec::new(),
             matcher: Matcher::new(Config::DEFAULT),
+            path_matcher: RefCell::new(Matcher::new(config)),
             search_active: false,
             show_hidden,
             indexing: true,
+            walk_cancel: Arc::new(AtomicBool::new(false)),
+            walk_done: Arc::new(AtomicBool::new(false)),
             tree_nodes: HashMap::new(),
             tree_visible: Vec::new(),
         };
         search.init_tree_root()?;
+        search.start_indexing();
         Ok(search)
     }
 
@@ -87,15 +89,43 @@ impl FileSearch {
     }
 
     pub fn refresh(&mut self) -> Result<()> {
-        let files = Self::collect_files(&self.root, self.show_hidden)?;
-        self.files = files;
-        self.matches = self.files.iter().enumerate().map(|(i, _)| (i, 0)).collect();
-        self.indexing = false;
+        self.start_indexing();
         let _ = self.refresh_tree_for_expanded();
         Ok(())
     }
 
-    fn sort_key(path: &PathBuf, is_dir: bool) -> Vec<(u8, String)> {
+    /// Walk the root again on a background thread, replacing the indexed
+    /// files. The old matches stay on show until the new ones are ready.
+    fn start_indexing(&mut self) {
+        self.walk_cancel.store(true, Ordering::Relaxed);
+        self.files.restart(false);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let done = Arc::new(AtomicBool::new(false));
+        let injector = self.files.injector();
+        let root = self.root.clone();
+        let show_hidden = self.show_hidden;
+        let (worker_cancel, worker_done) = (cancel.clone(), done.clone());
+        thread::spawn(move || {
+            walk_files(&root, show_hidden, &injector, &worker_cancel);
+            worker_done.store(true, Ordering::Release);
+        });
+        self.walk_cancel = cancel;
+        self.walk_done = done;
+        self.indexing = true;
+    }
+
+    /// Take in files found and matches made since the last call, waiting
+    /// briefly for the matcher. Returns whether anything shown changed.
+    pub fn tick(&mut self) -> bool {
+        let status = self.files.tick(10);
+        let finished = self.indexing && self.walk_done.load(Ordering::Acquire);
+        if finished {
+            self.indexing = false;
+        }
+        status.changed || finished
+    }
+
+    fn sort_key(path: &Path, is_dir: bool) -> Vec<(u8, String)> {
         let components: Vec<_> = path.components().collect();
         let mut key = Vec::new();
 
@@ -109,33 +139,6 @@ impl FileSearch {
         key
     }
 
-    pub(crate) fn collect_files(root: &PathBuf, show_hidden: bool) -> Result<Vec<PathBuf>> {
-        let mut files = Vec::new();
-
-        for entry in WalkBuilder::new(root)
-            .hidden(!show_hidden) // hidden(true) = skip hidden files
-            .git_ignore(true)
-            .git_exclude(true)
-            .filter_entry(|e| e.file_name() != ".git")
-            .build()
-            .filter_map(|e| e.ok())
-        {
-            if let Ok(relative) = entry.path().strip_prefix(root) {
-                if relative.as_os_str().is_empty() {
-                    continue;
-                }
-
-                let is_file = entry.file_type().map_or(false, |ft| ft.is_file());
-                if is_file {
-                    files.push(relative.to_path_buf());
-                }
-            }
-        }
-
-        files.sort();
-        Ok(files)
-    }
-
     #[allow(dead_code)]
     pub(crate) fn collect_entries(
         root: &PathBuf,
@@ -157,8 +160,8 @@ impl FileSearch {
                     continue;
                 }
 
-                let is_file = entry.file_type().map_or(false, |ft| ft.is_file());
-                let is_dir = entry.file_type().map_or(false, |ft| ft.is_dir());
+                let is_file = entry.file_type().is_some_and(|ft| ft.is_file());
+                let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
 
                 if is_file {
                     files.push(relative.to_path_buf());
@@ -199,12 +202,6 @@ impl FileSearch {
         Ok((files, entries))
     }
 
-    pub fn apply_index(&mut self, files: Vec<PathBuf>) {
-        self.files = files;
-        self.matches = self.files.iter().enumerate().map(|(i, _)| (i, 0)).collect();
-        self.indexing = false;
-    }
-
     fn init_tree_root(&mut self) -> Result<()> {
         self.tree_nodes.clear();
         self.tree_nodes.insert(
@@ -263,7 +260,7 @@ impl FileSearch {
                 continue;
             };
             let rel_path = relative.to_path_buf();
-            let is_dir = entry.file_type().map_or(false, |ft| ft.is_dir());
+            let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
             let name = entry.file_name().to_string_lossy().to_lowercase();
             let prefix = if is_dir { 0 } else { 1 };
             children.push((prefix, name, rel_path.clone(), is_dir));
@@ -277,7 +274,7 @@ impl FileSearch {
                 });
         }
 
-        children.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+        children.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
 
         let child_paths: Vec<PathBuf> = children.into_iter().map(|(_, _, path, _)| path).collect();
 
@@ -351,12 +348,33 @@ impl FileSearch {
         self.tree_visible.get(index)
     }
 
+    /// Match the indexed files against `query`, written in fzf's extended
+    /// syntax: space-separated terms that must all match, each fuzzy unless
+    /// written `'exact`, `^prefix`, `suffix$` or `^whole$`, and negated
+    /// with a leading `!`. Matching runs on worker threads; `tick` picks up
+    /// the results.
     pub fn update_query(&mut self, query: &str) {
         self.search_active = !query.is_empty();
+        if query == self.query {
+            return;
+        }
+        // An extended query only narrows the matches, so they are filtered
+        // rather than every file scored again. Not after a `$` or `\`
+        // though: typing on turns `foo$` from a suffix into a fuzzy term,
+        // and `\` escapes whatever comes next.
+        let append = query.starts_with(&self.query) && !self.query.ends_with(['$', '\\']);
+        self.files
+            .pattern
+            .reparse(0, query, CaseMatching::Smart, Normalization::Smart, append);
+        self.query = query.to_string();
+        self.tick();
+    }
 
+    /// Fuzzy-rank arbitrary `candidates` against `query` with the same
+    /// matcher used for file paths. Returns candidate indices, best first.
+    pub fn rank(&mut self, query: &str, candidates: &[String]) -> Vec<usize> {
         if query.is_empty() {
-            self.matches = self.files.iter().enumerate().map(|(i, _)| (i, 0)).collect();
-            return;
+            return (0..candidates.len()).collect();
         }
 
         let mut scored: Vec<(usize, u32)> = Vec::new();
@@ -364,22 +382,20 @@ impl FileSearch {
         let mut query_buf = Vec::new();
         let needle = Utf32Str::new(query, &mut query_buf);
 
-        for (idx, path) in self.files.iter().enumerate() {
-            let path_str = path.to_string_lossy();
-            let haystack = Utf32Str::new(&path_str, &mut buf);
-
+        for (idx, candidate) in candidates.iter().enumerate() {
+            let haystack = Utf32Str::new(candidate, &mut buf);
             if let Some(score) = self.matcher.fuzzy_match(haystack, needle) {
                 scored.push((idx, score as u32));
             }
         }
 
-        scored.sort_by(|a, b| b.1.cmp(&a.1));
-        self.matches = scored;
+        scored.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        scored.into_iter().map(|(idx, _)| idx).collect()
     }
 
     pub fn match_count(&self) -> usize {
         if self.search_active {
-            self.matches.len()
+            self.files.snapshot().matched_item_count() as usize
         } else {
             self.tree_visible.len()
         }
@@ -389,17 +405,29 @@ impl FileSearch {
         self.visible_entry_at(index)
     }
 
-    pub fn match_path_at(&self, index: usize) -> Option<(&PathBuf, u32)> {
-        self.matches
-            .get(index)
-            .and_then(|(idx, score)| self.files.get(*idx).map(|p| (p, *score)))
+    pub fn match_path_at(&self, index: usize) -> Option<PathMatch<'_>> {
+        let snapshot = self.files.snapshot();
+        let item = snapshot.get_matched_item(index as u32)?;
+        // Snapshots keep the order but not the scores, so matching the one
+        // path again gives both its score and the chars to highlight
+        let mut indices = Vec::new();
+        let score = snapshot
+            .pattern()
+            .column_pattern(0)
+            .indices(item.matcher_columns[0].slice(..), &mut self.path_matcher.borrow_mut(), &mut indices)
+            .unwrap_or(0);
+        indices.sort_unstable();
+        indices.dedup();
+        Some(PathMatch {
+            path: item.data,
+            score,
+            indices,
+        })
     }
 
     pub fn get_match(&self, index: usize) -> Option<PathBuf> {
         if self.search_active {
-            self.matches
-                .get(index)
-                .map(|(idx, _)| self.root.join(&self.files[*idx]))
+            self.match_path_at(index).map(|found| self.root.join(found.path))
         } else {
             self.get_visible_entry(index)
                 .filter(|e| !e.is_dir)
@@ -407,3 +435,170 @@ impl FileSearch {
         }
     }
 }
+
+/// Push every file under `root` into `injector`, relative to `root`,
+/// until `cancel` is set.
+fn walk_files(root: &Path, show_hidden: bool, injector: &Injector<PathBuf>, cancel: &AtomicBool) {
+    for entry in WalkBuilder::new(root)
+        .hidden(!show_hidden) // hidden(true) = skip hidden files
+        .git_ignore(true)
+        .git_exclude(true)
+        .filter_entry(|e| e.file_name() != ".git")
+        .build()
+        .filter_map(|e| e.ok())
+    {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+            continue;
+        }
+        if let Ok(relative) = entry.path().strip_prefix(root) {
+            injector.push(relative.to_path_buf(), |path, columns| {
+                columns[0] = path.to_string_lossy().as_ref().into();
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A project with these files, removed again when dropped.
+    struct Project(PathBuf);
+
+    impl Project {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("teditor-search-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            for file in [
+                "README.md",
+                "docs/ui_view.md",
+                "docs/price$list.txt",
+                "docs/src_notes.txt",
+                "src/main.rs",
+                "src/my_app_in.rs",
+                "src/search.rs",
+                "src/ui/editor_view.rs",
+                "src/ui/search_view.rs",
+                "tests/search_test.rs",
+                "tests/ui_view_test.rs",
+            ] {
+                let path = root.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, "").unwrap();
+            }
+            Self(root)
+        }
+
+        /// Paths matching `query`, sorted.
+        fn matches(&self, query: &str) -> Vec<String> {
+            let mut search = FileSearch::new_deferred(self.0.clone(), true).unwrap();
+            while search.indexing {
+                search.tick();
+            }
+            search.update_query(query);
+            while search.files.tick(10).running {}
+            let mut paths: Vec<String> = (0..search.match_count())
+                .filter_map(|idx| search.match_path_at(idx))
+                .map(|found| found.path.to_string_lossy().replace('\\', "/"))
+                .collect();
+            paths.sort();
+            paths
+        }
+    }
+
+    impl Drop for Project {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn fuzzy_terms_match_in_order_with_gaps() {
+        let project = Project::new("fuzzy");
+        assert_eq!(project.matches("srchvw"), ["src/ui/search_view.rs"]);
+    }
+
+    #[test]
+    fn quoted_terms_match_exactly() {
+        let project = Project::new("substring");
+        assert_eq!(project.matches("main"), ["src/main.rs", "src/my_app_in.rs"]);
+        assert_eq!(project.matches("'main"), ["src/main.rs"]);
+    }
+
+    #[test]
+    fn caret_terms_match_the_start() {
+        let project = Project::new("prefix");
+        assert_eq!(
+            project.matches("^src"),
+            [
+                "src/main.rs",
+                "src/my_app_in.rs",
+                "src/search.rs",
+                "src/ui/editor_view.rs",
+                "src/ui/search_view.rs",
+            ]
+        );
+    }
+
+    #[test]
+    fn dollar_terms_match_the_end() {
+        let project = Project::new("postfix");
+        assert_eq!(project.matches(".md$"), ["README.md", "docs/ui_view.md"]);
+    }
+
+    #[test]
+    fn caret_and_dollar_match_the_whole_path() {
+        let project = Project::new("exact");
+        assert_eq!(project.matches("^src/search.rs$"), ["src/search.rs"]);
+        assert!(project.matches("^search.rs$").is_empty());
+    }
+
+    #[test]
+    fn bang_terms_exclude() {
+        let project = Project::new("negated");
+        assert_eq!(
+            project.matches("!src !test"),
+            ["README.md", "docs/price$list.txt", "docs/ui_view.md"]
+        );
+        assert_eq!(project.matches("!^src !.md$ !.txt$"), ["tests/search_test.rs", "tests/ui_view_test.rs"]);
+    }
+
+    #[test]
+    fn terms_separated_by_spaces_must_all_match() {
+        let project = Project::new("and");
+        assert_eq!(
+            project.matches("ui view .rs$ !test"),
+            ["src/ui/editor_view.rs", "src/ui/search_view.rs"]
+        );
+    }
+
+    #[test]
+    fn special_characters_can_be_escaped() {
+        let project = Project::new("escaped");
+        assert!(project.matches("\\^src").is_empty());
+        assert!(project.matches("main\\$").is_empty());
+        // Only matches with the `$` taken literally, not as an anchor
+        assert_eq!(project.matches("price\\$"), ["docs/price$list.txt"]);
+        assert_eq!(project.matches("^docs/price\\$"), ["docs/price$list.txt"]);
+    }
+
+    #[test]
+    fn typing_past_a_dollar_matches_again() {
+        let project = Project::new("typing");
+        let mut search = FileSearch::new_deferred(project.0.clone(), true).unwrap();
+        while search.indexing {
+            search.tick();
+        }
+        search.update_query("price$");
+        while search.files.tick(10).running {}
+        assert_eq!(search.match_count(), 0);
+        // A fuzzy term again, which has to look past the suffix matches
+        search.update_query("price$l");
+        while search.files.tick(10).running {}
+        assert_eq!(search.match_count(), 1);
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::{bail, Result};
+use ropey::Rope;
+use similar::{ChangeTag, TextDiff};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::editor::simple_hash;
+use crate::paths;
+use crate::save::{self, BackupMode};
+
+/// How often modified buffers are written to their swap file.
+pub const SWAP_INTERVAL: Duration = Duration::from_secs(4);
+
+const SWAP_HEADER: &str = "teditor-swap v1";
+
+/// Unsaved buffer contents found on disk for a file.
+pub struct Swap {
+    pub swap_path: PathBuf,
+    pub lines: Vec<String>,
+}
+
+/// Location of the swap file for `path`, like vim's `.swp`.
+pub fn swap_path(path: &Path) -> Option<PathBuf> {
+    let key = paths::state_key(path);
+    let name = format!(
+        "{:016x}-{}.swp",
+        simple_hash(&key.to_string_lossy()),
+        key.file_name().map(|n| n.to_string_lossy()).unwrap_or_default()
+    );
+    paths::state_dir().map(|dir| dir.join("swap").join(name))
+}
+
+/// Write the buffer text for `path` to its swap file, straight from the
+/// rope's chunks.
+pub fn write(path: &Path, text: &Rope) -> Result<()> {
+    let Some(swap) = swap_path(path) else {
+        bail!("no state directory");
+    };
+    write_at(&swap, path, text)
+}
+
+fn write_at(swap: &Path, path: &Path, text: &Rope) -> Result<()> {
+    if let Some(parent) = swap.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let key = paths::state_key(path);
+    save::write_with(swap, BackupMode::None, |out| {
+        write!(out, "{}\n{}\n", SWAP_HEADER, key.display())?;
+        text.chunks().try_for_each(|chunk| out.write_all(chunk.as_bytes()))
+    })?;
+    Ok(())
+}
+
+pub fn remove(path: &Path) {
+    if let Some(swap) = swap_path(path) {
+        let _ = fs::remove_file(swap);
+    }
+}
+
+/// Return the swap for `path` if it is newer than the file and holds text
+/// that differs from `current`.
+pub fn find_recoverable(path: &Path, current: &Rope) -> Option<Swap> {
+    recoverable(swap_path(path)?, path, current)
+}
+
+fn recoverable(swap_path: PathBuf, path: &Path, current: &Rope) -> Option<Swap> {
+    let swap_mtime = fs::metadata(&swap_path).and_then(|m| m.modified()).ok()?;
+    let file_mtime = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    if swap_mtime <= file_mtime {
+        return None;
+    }
+    let (_, lines) = read(&swap_path)?;
+    if *current == lines.join("\n") {
+        return None;
+    }
+    Some(Swap { swap_path, lines })
+}
+
+/// Delete swap files whose contents already match the file on disk.
+/// Swaps for missing files or with unsaved edits are left alone.
+pub fn remove_stale() {
+    if let Some(dir) = paths::state_dir() {
+        remove_stale_in(&dir.join("swap"));
+    }
+}
+
+fn remove_stale_in(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let swap_path = entry.path();
+        let Some((original, lines)) = read(&swap_path) else {
+            continue;
+        };
+        let Ok(bytes) = fs::read(&original) else {
+            continue;
+        };
+        let on_disk = crate::file_format::decode(&bytes).map(|(l, _)| l);
+        if on_disk.is_ok_and(|l| l == lines) {
+            let _ = fs::remove_file(&swap_path);
+        }
+    }
+}
+
+fn read(swap_path: &Path) -> Option<(PathBuf, Vec<String>)> {
+    let data = fs::read_to_string(swap_path).ok()?;
+    let mut parts = data.splitn(3, '\n');
+    if parts.next()? != SWAP_HEADER {
+        return None;
+    }
+    let original = PathBuf::from(parts.next()?);
+    let lines = parts.next().unwrap_or("").split('\n').map(String::from).collect();
+    Some((original, lines))
+}
+
+/// A line of the recovery diff shown to the user.
+pub enum DiffLine {
+    Context(String),
+    Removed(String),
+    Added(String),
+    Separator,
+}
+
+/// Diff from the file on disk to the swap contents, trimmed to the changed
+/// regions with a little context.
+pub fn diff(disk: &[String], swap: &[String]) -> Vec<DiffLine> {
+    let old = disk.join("\n");
+    let new = swap.join("\n");
+    let diff = TextDiff::from_lines(&old, &new);
+    let mut out = Vec::new();
+    for (i, group) in diff.grouped_ops(2).iter().enumerate() {
+        if i > 0 {
+            out.push(DiffLine::Separator);
+        }
+        for op in group {
+            for change in diff.iter_changes(op) {
+                let text = change.value().trim_end_matches('\n').to_string();
+                out.push(match change.tag() {
+                    ChangeTag::Equal => DiffLine::Context(text),
+                    ChangeTag::Delete => DiffLine::Removed(text),
+                    ChangeTag::Insert => DiffLine::Added(text),
+                });
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    /// A scratch directory, removed again when dropped.
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("teditor-swap-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("swap")).unwrap();
+            Self(dir)
+        }
+
+        /// A file holding `text` and a swap for it holding `swapped`.
+        fn pair(&self, name: &str, text: &str, swapped: &str) -> (PathBuf, PathBuf) {
+            let path = self.0.join(name);
+            fs::write(&path, text).unwrap();
+            let swap = self.0.join("swap").join(format!("{}.swp", name));
+            write_at(&swap, &path, &Rope::from_str(swapped)).unwrap();
+            (path, swap)
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn age(path: &Path, secs: u64) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(secs)).unwrap();
+    }
+
+    #[test]
+    fn swaps_round_trip_through_the_file() {
+        let dir = Dir::new("read");
+        let (path, swap) = dir.pair("a.txt", "", "one\ntwo");
+        assert_eq!(
+            read(&swap),
+            Some((paths::state_key(&path), vec!["one".to_string(), "two".to_string()]))
+        );
+    }
+
+    #[test]
+    fn newer_swaps_with_other_text_are_recoverable() {
+        let dir = Dir::new("newer");
+        let (path, swap) = dir.pair("a.txt", "saved", "edited");
+        age(&path, 60);
+        let found = recoverable(swap.clone(), &path, &Rope::from_str("saved")).unwrap();
+        assert_eq!(found.swap_path, swap);
+        assert_eq!(found.lines, vec!["edited"]);
+    }
+
+    #[test]
+    fn swaps_older_than_the_file_are_ignored() {
+        let dir = Dir::new("older");
+        let (path, swap) = dir.pair("a.txt", "saved", "edited");
+        age(&swap, 60);
+        assert!(recoverable(swap, &path, &Rope::from_str("saved")).is_none());
+    }
+
+    #[test]
+    fn swaps_matching_the_buffer_are_ignored() {
+        let dir = Dir::new("same");
+        let (path, swap) = dir.pair("a.txt", "saved", "one\ntwo");
+        age(&path, 60);
+        assert!(recoverable(swap, &path, &Rope::from_str("one\ntwo")).is_none());
+    }
+
+    #[test]
+    fn stale_swaps_are_removed_and_unsaved_ones_kept() {
+        let dir = Dir::new("stale");
+        let (_, stale) = dir.pair("saved.txt", "one\r\ntwo\r\n", "one\ntwo");
+        let (_, unsaved) = dir.pair("edited.txt", "one", "one\ntwo");
+        let (gone, orphan) = dir.pair("gone.txt", "one", "one");
+        fs::remove_file(gone).unwrap();
+
+        remove_stale_in(&dir.0.join("swap"));
+
+        assert!(!stale.exists());
+        assert!(unsaved.exists());
+        assert!(orphan.exists());
+    }
+}
@@ -0,0 +1,288 @@
+use ropey::Rope;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use syntect::dumps;
+use syntect::parsing::{SyntaxDefinition, SyntaxReference, SyntaxSet};
+
+use crate::editor::hash_chunks;
+use crate::paths;
+use crate::text;
+
+/// Syntax used when nothing is detected. Buffers in it are not parsed.
+pub const PLAIN_TEXT: &str = "Plain Text";
+
+/// syntect's bundled syntaxes plus any `.sublime-syntax` files in
+/// `<config dir>/syntaxes`. Files that fail to parse are left out and
+/// reported as "path: reason".
+///
+/// Adding user syntaxes means linking the whole set again, which is slow,
+/// so the result is dumped to the cache directory and reused until the
+/// files change.
+pub fn load() -> (SyntaxSet, Vec<String>) {
+    let files = user_syntaxes();
+    if files.is_empty() {
+        return (SyntaxSet::load_defaults_newlines(), Vec::new());
+    }
+    let dump = paths::cache_dir().map(|dir| dir.join(format!("syntaxes-{:016x}.packdump", fingerprint(&files))));
+    if let Some(set) = dump.as_ref().and_then(|path| dumps::from_dump_file(path).ok()) {
+        return (set, Vec::new());
+    }
+
+    let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+    let mut errors = Vec::new();
+    for path in files {
+        let name = path.file_stem().map(|stem| stem.to_string_lossy().to_string());
+        let loaded = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|source| {
+                SyntaxDefinition::load_from_str(&source, true, name.as_deref()).map_err(|e| e.to_string())
+            });
+        match loaded {
+            Ok(definition) => builder.add(definition),
+            Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+    let set = builder.build();
+    // Only a clean build is cached, so broken files keep being reported
+    if let Some(dump) = dump.filter(|_| errors.is_empty()) {
+        let _ = write_dump(&set, &dump);
+    }
+    (set, errors)
+}
+
+fn user_syntaxes() -> Vec<PathBuf> {
+    let Some(entries) = paths::config_dir().and_then(|dir| fs::read_dir(dir.join("syntaxes")).ok()) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sublime-syntax"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Changes when any syntax file is added, removed or modified, or when
+/// teditor (and with it the bundled syntaxes) is updated.
+fn fingerprint(files: &[PathBuf]) -> u64 {
+    // Hashed with the stable FNV hash so the name survives toolchain updates
+    let mut parts = vec![env!("CARGO_PKG_VERSION").to_string()];
+    for path in files {
+        parts.push(path.to_string_lossy().into_owned());
+        if let Ok(meta) = fs::metadata(path) {
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_nanos());
+            parts.push(format!("{}:{}", meta.len(), modified));
+        }
+    }
+    hash_chunks(parts.iter().flat_map(|part| [part.as_str(), "\0"]))
+}
+
+/// Write `set` to `path`, replacing dumps of older syntax files.
+fn write_dump(set: &SyntaxSet, path: &Path) -> anyhow::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    for entry in fs::read_dir(dir)?.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("syntaxes-") && name.ends_with(".packdump") {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+    dumps::dump_to_file(set, path)?;
+    Ok(())
+}
+
+/// Files known by name that syntect has no entry for, with the syntaxes
+/// to try for them in order. Also matched with a suffix after a dot, so
+/// `Dockerfile.dev` and `.env.local` count, unless the suffix is itself a
+/// known extension as in `Dockerfile.md`.
+const FILENAMES: &[(&str, &[&str])] = &[
+    ("Dockerfile", &["dockerfile", "sh"]),
+    ("Containerfile", &["dockerfile", "sh"]),
+    (".env", &["dotenv", "sh"]),
+    ("PKGBUILD", &["sh"]),
+    ("Jenkinsfile", &["groovy"]),
+    ("CMakeLists.txt", &["cmake"]),
+];
+
+/// Interpreter and modeline names that are not a syntax name or extension.
+const ALIASES: &[(&str, &str)] = &[
+    ("node", "js"),
+    ("nodejs", "js"),
+    ("javascript", "js"),
+    ("shell-script", "sh"),
+    ("shell", "sh"),
+    ("dash", "sh"),
+    ("ksh", "sh"),
+    ("text", "txt"),
+    ("plain", "txt"),
+];
+
+/// Syntax for a file. In order of precedence: a vim or emacs modeline,
+/// the full file name, the extension, a known name with a suffix, then the
+/// first line (shebangs and markers like `<?php`).
+pub fn detect<'a>(set: &'a SyntaxSet, path: &Path, text: &Rope) -> Option<&'a SyntaxReference> {
+    if let Some(syntax) = modeline(text).and_then(|name| by_token(set, &name)) {
+        return Some(syntax);
+    }
+    let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    if let Some(syntax) = set.find_syntax_by_extension(&file_name) {
+        return Some(syntax);
+    }
+    let known = FILENAMES.iter().find(|(name, _)| {
+        file_name
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    });
+    let by_name = known.and_then(|(_, tokens)| tokens.iter().find_map(|token| by_token(set, token)));
+    let extension = path.extension().map(|ext| ext.to_string_lossy());
+    let by_extension = extension.and_then(|ext| set.find_syntax_by_extension(&ext));
+    let exact = known.is_some_and(|(name, _)| *name == file_name);
+    if let Some(syntax) = if exact { by_name.or(by_extension) } else { by_extension.or(by_name) } {
+        return Some(syntax);
+    }
+    let first = text::line(text, 0);
+    set.find_syntax_by_first_line(&first)
+        .or_else(|| shebang(&first).and_then(|interpreter| by_token(set, &interpreter)))
+}
+
+/// Look up a syntax by extension or case-insensitive name.
+fn by_token<'a>(set: &'a SyntaxSet, token: &str) -> Option<&'a SyntaxReference> {
+    let token = token.to_lowercase();
+    let token = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == token)
+        .map_or(token.as_str(), |(_, to)| to);
+    set.find_syntax_by_token(token)
+}
+
+/// Interpreter named by a `#!` line, without its version: `python` for
+/// both `#!/usr/bin/python3.11` and `#!/usr/bin/env -S python3 -u`.
+fn shebang(line: &str) -> Option<String> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+    }
+    let name = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Lines at each end of a file searched for modelines, as in vim.
+const MODELINE_LINES: usize = 5;
+
+/// Syntax named by a modeline near the top or bottom of the file: vim's
+/// `vim: set ft=python:` / `vi: syntax=sh` or emacs's `-*- mode: ruby -*-`.
+fn modeline(text: &Rope) -> Option<String> {
+    let count = text.len_lines();
+    let head = 0..MODELINE_LINES.min(count);
+    let tail = count.saturating_sub(MODELINE_LINES).max(head.end)..count;
+    head.chain(tail)
+        .map(|idx| text::line(text, idx))
+        .find_map(|line| vim_modeline(&line).or_else(|| emacs_modeline(&line)))
+}
+
+fn vim_modeline(line: &str) -> Option<String> {
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        let at = line.find(marker)?;
+        // The marker must start a word, so "navi:" is not a modeline
+        let word_start = line[..at].chars().next_back().is_none_or(char::is_whitespace);
+        word_start.then(|| at + marker.len())
+    })?;
+    line[start..]
+        .split([' ', ':', '\t'])
+        .find_map(|option| {
+            let (key, value) = option.split_once('=')?;
+            matches!(key, "ft" | "filetype" | "syn" | "syntax").then(|| value.to_string())
+        })
+        .filter(|value| !value.is_empty())
+}
+
+fn emacs_modeline(line: &str) -> Option<String> {
+    let start = line.find("-*-")? + 3;
+    let end = start + line[start..].find("-*-")?;
+    let inside = line[start..end].trim();
+    if !inside.contains(':') {
+        return (!inside.is_empty()).then(|| inside.to_string());
+    }
+    inside.split(';').find_map(|variable| {
+        let (key, value) = variable.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case("mode")
+            .then(|| value.trim().to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(path: &str, text: &str) -> Option<String> {
+        let set = SyntaxSet::load_defaults_newlines();
+        detect(&set, Path::new(path), &Rope::from_str(text)).map(|syntax| syntax.name.clone())
+    }
+
+    #[test]
+    fn shebang_names_the_interpreter_without_its_version() {
+        assert_eq!(shebang("#!/usr/bin/python3.11"), Some("python".to_string()));
+        assert_eq!(shebang("#!/usr/bin/env -S python3 -u"), Some("python".to_string()));
+        assert_eq!(shebang("#!/usr/bin/env LANG=C ruby"), Some("ruby".to_string()));
+        assert_eq!(shebang("#!/bin/sh"), Some("sh".to_string()));
+        assert_eq!(shebang("# comment"), None);
+        assert_eq!(shebang("#!"), None);
+    }
+
+    #[test]
+    fn vim_modelines_give_the_filetype() {
+        assert_eq!(vim_modeline("# vim: set ft=python:"), Some("python".to_string()));
+        assert_eq!(vim_modeline("// vi: syntax=sh"), Some("sh".to_string()));
+        assert_eq!(vim_modeline("/* ex: ts=4 filetype=c */"), Some("c".to_string()));
+        assert_eq!(vim_modeline("navi: ft=python"), None);
+        assert_eq!(vim_modeline("vim: ts=4"), None);
+        assert_eq!(vim_modeline("vim: ft="), None);
+    }
+
+    #[test]
+    fn emacs_modelines_give_the_mode() {
+        assert_eq!(emacs_modeline("# -*- ruby -*-"), Some("ruby".to_string()));
+        assert_eq!(
+            emacs_modeline(";; -*- coding: utf-8; mode: lisp -*-"),
+            Some("lisp".to_string())
+        );
+        assert_eq!(emacs_modeline("# -*- coding: utf-8 -*-"), None);
+        assert_eq!(emacs_modeline("# -*- ruby"), None);
+    }
+
+    #[test]
+    fn modeline_beats_the_extension() {
+        assert_eq!(name("build.txt", "# vim: ft=python\nx = 1"), Some("Python".to_string()));
+        assert_eq!(name("notes.txt", "a\nb\nc\nd\ne\nf\ng\n# -*- ruby -*-"), Some("Ruby".to_string()));
+    }
+
+    #[test]
+    fn extension_beats_the_shebang() {
+        assert_eq!(name("run.py", "#!/bin/sh"), Some("Python".to_string()));
+        assert_eq!(name("run", "#!/usr/bin/env python3"), Some("Python".to_string()));
+    }
+
+    #[test]
+    fn known_names_match_only_whole_or_before_a_dot() {
+        let shell = Some("Bourne Again Shell (bash)".to_string());
+        assert_eq!(name(".env", ""), shell);
+        assert_eq!(name(".env.local", ""), shell);
+        assert_eq!(name("Dockerfile.dev", ""), shell);
+        assert_eq!(name(".environment.json", ""), Some("JSON".to_string()));
+    }
+
+    #[test]
+    fn a_known_extension_beats_a_known_name_prefix() {
+        assert_eq!(name(".env.schema.json", ""), Some("JSON".to_string()));
+        assert_eq!(name("Dockerfile.md", ""), Some("Markdown".to_string()));
+    }
+}
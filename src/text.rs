@@ -0,0 +1,52 @@
+use ropey::{Rope, RopeBuilder};
+use std::borrow::Cow;
+
+// Buffer text is kept in a rope with lines separated by a bare `\n` and no
+// final newline, so a file of N lines always has `len_lines() == N`. How
+// the file stores line endings lives in its `FileFormat` instead.
+
+/// Line `idx` without its newline; empty past the end.
+pub fn line(rope: &Rope, idx: usize) -> Cow<'_, str> {
+    if idx >= rope.len_lines() {
+        return Cow::Borrowed("");
+    }
+    let slice = rope.line(idx);
+    let len = slice.len_chars();
+    if len > 0 && slice.char(len - 1) == '\n' {
+        slice.slice(..len - 1).into()
+    } else {
+        slice.into()
+    }
+}
+
+/// Length of line `idx` in chars, not counting its newline.
+pub fn line_len(rope: &Rope, idx: usize) -> usize {
+    if idx >= rope.len_lines() {
+        return 0;
+    }
+    let len = rope.line(idx).len_chars();
+    if idx + 1 < rope.len_lines() {
+        len - 1
+    } else {
+        len
+    }
+}
+
+pub fn lines(rope: &Rope) -> impl Iterator<Item = Cow<'_, str>> {
+    (0..rope.len_lines()).map(move |idx| line(rope, idx))
+}
+
+pub fn to_lines(rope: &Rope) -> Vec<String> {
+    lines(rope).map(Cow::into_owned).collect()
+}
+
+pub fn from_lines(lines: &[String]) -> Rope {
+    let mut builder = RopeBuilder::new();
+    for (idx, line) in lines.iter().enumerate() {
+        if idx > 0 {
+            builder.append("\n");
+        }
+        builder.append(line);
+    }
+    builder.finish()
+}
@@ -0,0 +1,218 @@
+use ratatui::style::{Color, Style};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use syntect::highlighting::{Color as SyntectColor, Highlighter, Theme, ThemeSet};
+use syntect::parsing::Scope;
+
+use crate::paths;
+
+pub const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+/// Syntax themes to choose from: the ones bundled with syntect plus any
+/// `.tmTheme` files in `<config dir>/themes`, named after the file. A file
+/// named like a bundled theme replaces it. Loaded once and shared with
+/// every buffer.
+pub struct Themes {
+    themes: BTreeMap<String, Arc<Theme>>,
+    /// Theme files that failed to parse, as "path: reason"
+    pub errors: Vec<String>,
+}
+
+impl Themes {
+    pub fn load() -> Self {
+        let bundled = ThemeSet::load_defaults().themes;
+        let mut themes = Self {
+            themes: bundled.into_iter().map(|(name, theme)| (name, Arc::new(theme))).collect(),
+            errors: Vec::new(),
+        };
+        if let Some(dir) = paths::config_dir() {
+            themes.load_dir(&dir.join("themes"));
+        }
+        themes
+    }
+
+    fn load_dir(&mut self, dir: &Path) {
+        // No themes directory is the common case, not an error
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let is_theme = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("tmtheme"));
+            let Some(name) = path.file_stem().filter(|_| is_theme) else {
+                continue;
+            };
+            match ThemeSet::get_theme(&path) {
+                Ok(theme) => {
+                    self.themes.insert(name.to_string_lossy().to_string(), Arc::new(theme));
+                }
+                Err(e) => self.errors.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+    }
+
+    /// Theme names in alphabetical order.
+    pub fn names(&self) -> Vec<String> {
+        self.themes.keys().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<Theme>> {
+        self.themes.get(name)
+    }
+}
+
+/// Name of the theme to start with: `$TEDITOR_THEME`, then the one set in
+/// the config, then the default, skipping names that aren't loaded.
+pub fn initial_name(themes: &Themes, configured: Option<&str>) -> String {
+    std::env::var("TEDITOR_THEME")
+        .ok()
+        .into_iter()
+        .chain(configured.map(str::to_string))
+        .find(|name| themes.get(name).is_some())
+        .unwrap_or_else(|| DEFAULT_THEME.to_string())
+}
+
+/// Colours for the UI around the text, taken from the syntax theme so
+/// that lists, bars and borders sit on the same background as the code.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    pub background: Color,
+    pub foreground: Color,
+    /// Highlighted list entries and the active tab
+    pub selection: Color,
+    pub selection_fg: Color,
+    /// Line numbers, unfocused borders and other secondary text
+    pub gutter: Color,
+    pub status_bg: Color,
+    pub status_fg: Color,
+    /// Focused borders, titles and directories
+    pub accent: Color,
+    /// Text on accent and other bright backgrounds
+    pub accent_fg: Color,
+    /// Background of find matches, and of the current one
+    pub match_bg: Color,
+    pub current_match_bg: Color,
+    /// Added and removed text in diffs, previews and merges
+    pub added: Color,
+    pub removed: Color,
+    /// External changes and unresolved conflicts
+    pub warning: Color,
+}
+
+impl Default for Palette {
+    /// The terminal's own colours, as used before themes reached the UI.
+    fn default() -> Self {
+        Self {
+            background: Color::Reset,
+            foreground: Color::Reset,
+            selection: Color::Cyan,
+            selection_fg: Color::Black,
+            gutter: Color::DarkGray,
+            status_bg: Color::DarkGray,
+            status_fg: Color::White,
+            accent: Color::Cyan,
+            accent_fg: Color::Black,
+            match_bg: Color::Rgb(80, 70, 20),
+            current_match_bg: Color::Rgb(160, 110, 0),
+            added: Color::Green,
+            removed: Color::Red,
+            warning: Color::Yellow,
+        }
+    }
+}
+
+impl Palette {
+    pub fn from_theme(theme: &Theme) -> Self {
+        let fallback = Self::default();
+        let settings = &theme.settings;
+        let (Some(bg), Some(fg)) = (settings.background, settings.foreground) else {
+            return fallback;
+        };
+        // Themes rarely colour anything but text, so most of the UI is
+        // blended between the background and foreground
+        let accent = settings.accent.unwrap_or_else(|| scope_fg(theme, "keyword"));
+        // Diff colours come from the scopes themes give them, else from
+        // whichever common scope is usually coloured alike
+        let scoped = |scopes: &[&str], fallback: Color| {
+            scopes
+                .iter()
+                .map(|scope| scope_fg(theme, scope))
+                .find(|&color| color != fg)
+                .map_or(fallback, rgb)
+        };
+        Self {
+            background: rgb(bg),
+            foreground: rgb(fg),
+            selection: rgb(settings.selection.map_or_else(|| mix(bg, fg, 0.3), |c| over(c, bg))),
+            selection_fg: rgb(fg),
+            gutter: rgb(settings.gutter_foreground.map_or_else(|| mix(bg, fg, 0.45), |c| over(c, bg))),
+            status_bg: rgb(mix(bg, fg, 0.18)),
+            status_fg: rgb(fg),
+            accent: rgb(accent),
+            accent_fg: rgb(bg),
+            match_bg: rgb(settings.find_highlight.map_or_else(|| mix(bg, accent, 0.3), |c| over(c, bg))),
+            current_match_bg: rgb(mix(bg, accent, 0.6)),
+            added: scoped(&["markup.inserted", "string"], fallback.added),
+            removed: scoped(&["markup.deleted", "invalid"], fallback.removed),
+            warning: scoped(&["markup.changed", "constant.numeric"], fallback.warning),
+        }
+    }
+
+    /// Plain text on the theme background.
+    pub fn base(&self) -> Style {
+        Style::default().bg(self.background).fg(self.foreground)
+    }
+
+    pub fn selected(&self) -> Style {
+        Style::default().bg(self.selection).fg(self.selection_fg)
+    }
+
+    pub fn status(&self) -> Style {
+        Style::default().bg(self.status_bg).fg(self.status_fg)
+    }
+
+    /// Titles of panels and popups.
+    pub fn title(&self) -> Style {
+        Style::default().fg(self.accent)
+    }
+
+    /// Toggles that are on.
+    pub fn marked(&self) -> Style {
+        Style::default().bg(self.accent).fg(self.accent_fg)
+    }
+
+    /// Bars that need attention, such as the status bar after an external
+    /// change.
+    pub fn alert(&self) -> Style {
+        Style::default().bg(self.warning).fg(self.accent_fg)
+    }
+}
+
+/// Foreground the theme gives `scope`; its plain foreground if none.
+fn scope_fg(theme: &Theme, scope: &str) -> SyntectColor {
+    let scope = Scope::new(scope).expect("valid scope");
+    Highlighter::new(theme).style_for_stack(&[scope]).foreground
+}
+
+fn rgb(color: SyntectColor) -> Color {
+    Color::Rgb(color.r, color.g, color.b)
+}
+
+/// `color` drawn with its alpha over `bg`, as terminals have no alpha.
+fn over(color: SyntectColor, bg: SyntectColor) -> SyntectColor {
+    mix(bg, color, color.a as f32 / 255.0)
+}
+
+/// `amount` of the way from `from` to `to`.
+fn mix(from: SyntectColor, to: SyntectColor, amount: f32) -> SyntectColor {
+    let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount).round() as u8;
+    SyntectColor {
+        r: channel(from.r, to.r),
+        g: channel(from.g, to.g),
+        b: channel(from.b, to.b),
+        a: 0xff,
+    }
+}
@@ -1,66 +1,184 @@
-use crate::app::App;
+use crate::app::{App, Mode};
+use crate::config::Autosave;
+use crate::editor::Editor;
+use crate::find::{FindBar, FindField};
+use crate::keymap::Action;
+use crate::theme::Palette;
+use crate::viewport::{LineLayout, Stop, Viewport, WrapMode};
 use ratatui::{
     prelude::*,
     widgets::{Block, Borders, Paragraph},
 };
+use std::ops::Range;
+use std::time::Duration;
+use unicode_width::UnicodeWidthStr;
+
+/// Find matches to overlay on a pane, and which one is current.
+struct Marks<'a> {
+    matches: &'a [(usize, Range<usize>)],
+    current: Option<usize>,
+}
 
 pub fn draw(frame: &mut Frame, app: &mut App) {
-    let Some(ref editor) = app.editor else {
+    let Some(active) = app.active_index() else {
         return;
     };
+    if let Some(ref mut find) = app.find {
+        find.refresh(&app.buffers[active].editor);
+    }
+    let find_height = match app.find {
+        Some(ref find) if find.show_replace => 2,
+        Some(_) => 1,
+        None => 0,
+    };
 
-    let chunks = Layout::default()
+    let layout = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Min(1),    // Editor
-            Constraint::Length(1), // Status bar
+            Constraint::Length(1),           // Buffer tabs
+            Constraint::Min(1),              // Panes
+            Constraint::Length(find_height), // Find bar
+            Constraint::Length(1),           // Status bar
         ])
         .split(frame.area());
+    draw_tabs(frame, app, layout[0]);
 
-    let editor_height = chunks[0].height.saturating_sub(2) as usize;
-    let inner_width = chunks[0].width.saturating_sub(2) as usize;
-    let line_count = editor.highlighted_lines().len().max(1);
-    let line_number_digits = line_count.to_string().len();
-    let line_number_width = line_number_digits + 1; // digits + space
-    let text_width = inner_width.saturating_sub(line_number_width).max(1);
-
-    // Calculate scroll offset to keep cursor in view (accounting for wrapping)
-    let (cursor_row, cursor_col) = editor.cursor_position();
-    let line_lengths = editor.line_lengths();
-    let safe_row = cursor_row.min(line_lengths.len().saturating_sub(1));
-    let mut cursor_visual_row = 0;
-    for i in 0..safe_row {
-        cursor_visual_row += wrapped_line_count(line_lengths.get(i).copied().unwrap_or(0), text_width);
+    let active_id = app.buffers[active].id;
+    let current_match = app
+        .find
+        .as_ref()
+        .and_then(|find| find.current(&app.buffers[active].editor));
+
+    // Scroll each pane to its cursor, then bring its buffer's lazy
+    // highlighting up to the last visible line before drawing
+    app.pane_areas = app.layout.areas(layout[1]);
+    for &(pane_id, area) in &app.pane_areas {
+        let Some(pane) = app.panes.get_mut(&pane_id) else {
+            continue;
+        };
+        let Some(buffer) = app.buffers.iter_mut().find(|b| b.id == pane.buffer) else {
+            continue;
+        };
+        let focused = pane_id == app.focused;
+        let cursor = if focused {
+            buffer.editor.cursor_position()
+        } else {
+            pane.cursor
+        };
+        let (_, text_width, height) = text_area(&buffer.editor, area);
+        pane.viewport
+            .update(&buffer.editor, cursor, focused, text_width, height);
+        if focused {
+            buffer.editor.page_height = height;
+        }
+        buffer.editor.highlight_until(pane.viewport.top + height);
+    }
+    for &(pane_id, area) in &app.pane_areas {
+        let Some(pane) = app.panes.get(&pane_id) else {
+            continue;
+        };
+        let Some(buffer) = app.buffers.iter().find(|b| b.id == pane.buffer) else {
+            continue;
+        };
+        let focused = pane_id == app.focused;
+        // Find applies to the active buffer, in whichever panes show it
+        let marks = app.find.as_ref().filter(|_| buffer.id == active_id).map(|find| Marks {
+            matches: &find.matches,
+            current: current_match.filter(|_| focused),
+        });
+        // Only the focused pane has a cursor, kept in the editor itself
+        let cursor = focused.then(|| buffer.editor.cursor_position());
+        draw_pane(frame, &buffer.editor, cursor, &pane.viewport, area, marks, &app.palette);
     }
-    let line_len = line_lengths.get(safe_row).copied().unwrap_or(0);
-    let effective_col = cursor_col.min(line_len);
-    let (wrap_row, col_in_wrap) = wrap_position(effective_col, text_width);
-    cursor_visual_row += wrap_row;
 
-    let scroll_offset = if cursor_visual_row >= editor_height {
-        cursor_visual_row - editor_height + 1
-    } else {
-        0
-    };
+    if let Some(ref find) = app.find {
+        draw_find(frame, find, current_match, layout[2], &app.palette);
+    }
+    draw_status(frame, app, layout[3]);
+}
+
+/// Line number width and the text columns and rows inside a pane's border.
+fn text_area(editor: &Editor, area: Rect) -> (usize, usize, usize) {
+    let height = area.height.saturating_sub(2) as usize;
+    let inner_width = area.width.saturating_sub(2) as usize;
+    let line_number_digits = editor.line_count().max(1).to_string().len();
+    let text_width = inner_width.saturating_sub(line_number_digits + 1).max(1);
+    (line_number_digits, text_width, height)
+}
+
+fn draw_pane(
+    frame: &mut Frame,
+    editor: &Editor,
+    cursor: Option<(usize, usize)>,
+    viewport: &Viewport,
+    area: Rect,
+    marks: Option<Marks>,
+    palette: &Palette,
+) {
+    let (line_number_digits, text_width, editor_height) = text_area(editor, area);
+    let line_number_width = line_number_digits + 1; // digits + space
 
     let mut visible_lines: Vec<Line> = Vec::with_capacity(editor_height);
-    let mut visual_row = 0;
-    let number_style = Style::default().fg(Color::DarkGray);
+    let number_style = Style::default().fg(palette.gutter);
+    let continued = format!("{:>width$} ", "↪", width = line_number_digits);
+    // Rows with text cut off by the left and right edges, when not wrapping
+    let mut cut_off = Vec::new();
 
-    for (line_idx, spans) in editor.highlighted_lines().iter().enumerate() {
-        let wrap_count = wrapped_line_count(line_lengths.get(line_idx).copied().unwrap_or(0), text_width);
-        if visual_row + wrap_count <= scroll_offset {
-            visual_row += wrap_count;
-            continue;
+    // Only the lines on screen are touched, starting from the viewport
+    let mut skip = viewport.wrap;
+    for line_idx in viewport.top..editor.line_count() {
+        if visible_lines.len() >= editor_height {
+            break;
         }
-
         let line_number = format!("{:width$} ", line_idx + 1, width = line_number_digits);
         let number_span = Span::styled(line_number, number_style);
-        let pad_span = Span::styled(" ".repeat(line_number_width), number_style);
-        let wrapped = wrap_spans(spans, text_width);
-        let start_in_line = scroll_offset.saturating_sub(visual_row);
-
-        for (wrap_idx, wrapped_spans) in wrapped.into_iter().enumerate().skip(start_in_line) {
+        let pad_span = Span::styled(continued.clone(), number_style);
+        let spans = editor.line_spans(line_idx);
+        let spans = spans.as_ref();
+        let overlaid;
+        let spans = match marks {
+            Some(ref marks) => {
+                let first = marks.matches.partition_point(|(row, _)| *row < line_idx);
+                let line_marks: Vec<(Range<usize>, Color)> = marks.matches[first..]
+                    .iter()
+                    .enumerate()
+                    .take_while(|(_, (row, _))| *row == line_idx)
+                    .map(|(i, (_, range))| {
+                        // Only the background: the syntax colour stays
+                        let bg = if marks.current == Some(first + i) {
+                            palette.current_match_bg
+                        } else {
+                            palette.match_bg
+                        };
+                        (range.clone(), bg)
+                    })
+                    .collect();
+                if line_marks.is_empty() {
+                    spans
+                } else {
+                    overlaid = overlay(spans, &line_marks);
+                    &overlaid
+                }
+            }
+            None => spans,
+        };
+        // Lay out only as far as the pane shows
+        let text = editor.line(line_idx);
+        let stop = if editor.wrap == WrapMode::Off {
+            Stop::Cell(0, viewport.left + text_width)
+        } else {
+            Stop::Cell(skip + editor_height - visible_lines.len(), 0)
+        };
+        let layout = LineLayout::new(&text, text_width, editor.tab_width, editor.wrap, stop);
+        let wrapped = place_spans(spans, &layout, viewport.left, text_width, editor.wrap);
+        if editor.wrap == WrapMode::Off {
+            let left = viewport.left > 0 && layout.width() > 0;
+            let right = layout.width() > viewport.left + text_width || layout.bytes() < text.len();
+            if left || right {
+                cut_off.push((visible_lines.len(), left, right));
+            }
+        }
+        for (wrap_idx, wrapped_spans) in wrapped.into_iter().enumerate().skip(skip) {
             if visible_lines.len() >= editor_height {
                 break;
             }
@@ -73,138 +191,290 @@ pub fn draw(frame: &mut Frame, app: &mut App) {
             line_spans.extend(wrapped_spans);
             visible_lines.push(Line::from(line_spans));
         }
-
-        visual_row += wrap_count;
-        if visible_lines.len() >= editor_height {
-            break;
-        }
+        skip = 0;
     }
 
+    let border_style = if cursor.is_some() {
+        Style::default().fg(palette.accent)
+    } else {
+        Style::default().fg(palette.gutter)
+    };
     let editor_widget = Paragraph::new(visible_lines).block(
         Block::default()
             .borders(Borders::ALL)
+            .border_style(border_style)
             .title(format!(" {} ", editor.filename()))
-            .title_style(Style::default().fg(Color::Cyan)),
+            .title_style(border_style),
     );
-    frame.render_widget(editor_widget, chunks[0]);
+    frame.render_widget(editor_widget, area);
+
+    // Markers go over the first and last text cells of the row
+    let text_x = area.x + 1 + line_number_width as u16;
+    let last_x = (text_x + text_width as u16 - 1).min(area.right().saturating_sub(2));
+    let marker_style = number_style;
+    for (screen_row, left, right) in cut_off {
+        let y = area.y + 1 + screen_row as u16;
+        let buf = frame.buffer_mut();
+        if left {
+            buf.set_string(text_x, y, "‹", marker_style);
+        }
+        if right {
+            buf.set_string(last_x, y, "›", marker_style);
+        }
+    }
+
+    if let Some((cursor, cursor_screen_row)) =
+        cursor.and_then(|cursor| Some((cursor, viewport.cursor_row(editor, cursor)?)))
+    {
+        let (row, col) = cursor;
+        let (_, cell) = viewport.layout(editor, row, Stop::Char(col)).cursor_cell(col);
+        let col_in_wrap = cell.saturating_sub(viewport.left);
+        let cursor_x = area.x + 1 + (line_number_width + col_in_wrap) as u16;
+        let cursor_y = area.y + 1 + cursor_screen_row as u16;
+        frame.set_cursor_position(Position::new(cursor_x, cursor_y));
+    }
+}
+
+/// Give the byte ranges `marks` of a highlighted line a background colour,
+/// splitting spans at their edges and keeping the syntax foreground.
+fn overlay(spans: &[(Style, String)], marks: &[(Range<usize>, Color)]) -> Vec<(Style, String)> {
+    let mut out = Vec::new();
+    let mut offset = 0;
+    for (style, text) in spans {
+        let end = offset + text.len();
+        let mut cuts = vec![offset, end];
+        for (range, _) in marks {
+            for edge in [range.start, range.end] {
+                if edge > offset && edge < end && text.is_char_boundary(edge - offset) {
+                    cuts.push(edge);
+                }
+            }
+        }
+        cuts.sort_unstable();
+        cuts.dedup();
+        for pair in cuts.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            let bg = marks
+                .iter()
+                .find(|(range, _)| range.start <= from && to <= range.end)
+                .map(|(_, bg)| *bg);
+            let piece_style = match bg {
+                Some(bg) => style.bg(bg),
+                None => *style,
+            };
+            out.push((piece_style, text[from - offset..to - offset].to_string()));
+        }
+        offset = end;
+    }
+    out
+}
+
+fn draw_find(frame: &mut Frame, find: &FindBar, current: Option<usize>, area: Rect, palette: &Palette) {
+    let count = if let Some(ref error) = find.error {
+        error.lines().last().unwrap_or_default().to_string()
+    } else if find.query.is_empty() {
+        String::new()
+    } else if let Some(idx) = current {
+        format!("{}/{}", idx + 1, find.matches.len())
+    } else if find.matches.is_empty() {
+        "no matches".to_string()
+    } else {
+        format!("{} matches", find.matches.len())
+    };
+    let hint = Style::default().fg(palette.gutter);
+    let toggle = |on: bool, label: &'static str| {
+        if on {
+            Span::styled(label, palette.marked())
+        } else {
+            Span::styled(label, hint)
+        }
+    };
+
+    let find_line = Line::from(vec![
+        Span::styled(" Find: ", palette.title()),
+        Span::raw(find.query.clone()),
+        Span::raw("  "),
+        Span::styled(count, Style::default().fg(palette.accent)),
+        Span::raw("  "),
+        toggle(find.regex, " .* "),
+        Span::raw(" "),
+        toggle(find.case_sensitive, " Aa "),
+        Span::raw(" "),
+        toggle(find.whole_word, " W "),
+        Span::styled(
+            "  Enter/↑: next/prev | Alt+R/C/W: regex/case/word | Tab: replace | Esc: close",
+            hint,
+        ),
+    ]);
+    let mut lines = vec![find_line];
+    if find.show_replace {
+        lines.push(Line::from(vec![
+            Span::styled(" Repl: ", palette.title()),
+            Span::raw(find.replacement.clone()),
+            Span::styled(
+                "  Ctrl+R: replace | Ctrl+A: replace all",
+                hint,
+            ),
+        ]));
+    }
+    frame.render_widget(Paragraph::new(lines).style(palette.status()), area);
+
+    let (row, len) = match find.field {
+        FindField::Query => (0, find.query.width()),
+        FindField::Replace => (1, find.replacement.width()),
+    };
+    frame.set_cursor_position(Position::new(area.x + 7 + len as u16, area.y + row));
+}
 
-    // Status bar
+/// How long ago, coarsely: "just now", "42s ago", "5m ago", "2h ago".
+fn ago(elapsed: Duration) -> String {
+    match elapsed.as_secs() {
+        0..5 => "just now".to_string(),
+        secs @ 5..60 => format!("{}s ago", secs),
+        secs @ 60..3600 => format!("{}m ago", secs / 60),
+        secs => format!("{}h ago", secs / 3600),
+    }
+}
+
+/// Status bar for the focused pane's buffer.
+fn draw_status(frame: &mut Frame, app: &App, area: Rect) {
+    let Some(buffer) = app.active_buffer() else {
+        return;
+    };
+    let editor = &buffer.editor;
     let modified_indicator = if editor.is_modified() { " [+]" } else { "" };
-    let external_change = if app.file_changed_externally {
-        " [CONFLICT - external change]"
+    let keys = &app.config.keys;
+    let external_change = if !buffer.file_changed_externally {
+        String::new()
+    } else if let Some(key) = keys.key_for(Mode::Edit, Action::Merge) {
+        format!(" [CONFLICT - external change, {}: merge]", key)
     } else {
-        ""
+        " [CONFLICT - external change]".to_string()
     };
     let (row, col) = editor.cursor_position();
+    let (undo_steps, redo_steps) = editor.undo_status();
+
+    let hints = app.status_message.clone().unwrap_or_else(|| {
+        let back = if app.config.autosave == Autosave::OnLeave { "save & back" } else { "back" };
+        keys.hints(
+            Mode::Edit,
+            &[
+                (&[Action::CommandPalette], "commands"),
+                (&[Action::Save], "save"),
+                (&[Action::Back], back),
+                (&[Action::Revert], "revert"),
+                (&[Action::Undo, Action::Redo], "undo/redo"),
+                (&[Action::PrevBuffer, Action::NextBuffer], "buffers"),
+                (&[Action::BufferPicker], "pick"),
+                (&[Action::CloseBuffer], "close"),
+                (&[Action::SplitRight, Action::SplitDown], "split"),
+            ],
+        )
+    });
+    let autosave = match editor.saved_at() {
+        Some(at) => format!("{}, saved {}", app.config.autosave_label(), ago(at.elapsed())),
+        None => app.config.autosave_label(),
+    };
 
     let status_text = format!(
-        " {}{}{}  |  Ln {}, Col {}  |  Esc: save & back | Ctrl+R: reload",
+        " {}{}{}  |  Ln {}, Col {}  |  {}  |  {}  |  {}  |  Undo {} Redo {}  |  {}",
         editor.filename(),
         modified_indicator,
         external_change,
         row + 1,
-        col + 1
+        col + 1,
+        editor.syntax_name(),
+        editor.format.describe(),
+        editor.wrap.label(),
+        undo_steps,
+        redo_steps,
+        hints
     );
 
-    let status_style = if app.file_changed_externally {
-        Style::default().bg(Color::Yellow).fg(Color::Black)
+    let status_style = if buffer.file_changed_externally {
+        app.palette.alert()
     } else {
-        Style::default().bg(Color::DarkGray).fg(Color::White)
+        app.palette.status()
     };
 
-    let status = Paragraph::new(status_text).style(status_style);
-    frame.render_widget(status, chunks[1]);
-
-    // Position cursor
-    let cursor_visual_col = line_number_width + col_in_wrap;
-    let cursor_screen_row = cursor_visual_row.saturating_sub(scroll_offset);
-    if cursor_screen_row < editor_height {
-        let cursor_x = chunks[0].x + 1 + cursor_visual_col as u16;
-        let cursor_y = chunks[0].y + 1 + cursor_screen_row as u16;
-        frame.set_cursor_position(Position::new(cursor_x, cursor_y));
-    }
-}
-
-fn wrapped_line_count(len: usize, width: usize) -> usize {
-    if width == 0 {
-        return 1;
-    }
-    if len == 0 {
-        return 1;
-    }
-    (len - 1) / width + 1
+    // The autosave state sits at the right so messages aren't pushed off
+    let [left, right] = Layout::horizontal([
+        Constraint::Min(0),
+        Constraint::Length(autosave.width() as u16 + 2),
+    ])
+    .areas(area);
+    frame.render_widget(Paragraph::new(status_text).style(status_style), left);
+    frame.render_widget(
+        Paragraph::new(format!("{} ", autosave)).style(status_style).right_aligned(),
+        right,
+    );
 }
 
-fn wrap_position(col: usize, width: usize) -> (usize, usize) {
-    if width == 0 || col == 0 {
-        return (0, 0);
+/// One tab per open buffer, the active one highlighted.
+fn draw_tabs(frame: &mut Frame, app: &App, area: Rect) {
+    let mut spans = Vec::new();
+    let active = app.active_index();
+    for (idx, buffer) in app.buffers.iter().enumerate() {
+        let modified = if buffer.editor.is_modified() { " +" } else { "" };
+        let conflict = if buffer.file_changed_externally { " !" } else { "" };
+        let label = format!(" {}{}{} ", buffer.editor.filename(), modified, conflict);
+        let style = if Some(idx) == active {
+            app.palette.selected().add_modifier(Modifier::BOLD)
+        } else {
+            app.palette.status()
+        };
+        spans.push(Span::styled(label, style));
+        spans.push(Span::raw(" "));
     }
-    let wrap_row = (col - 1) / width;
-    let col_in_wrap = (col - 1) % width + 1;
-    (wrap_row, col_in_wrap)
+    frame.render_widget(Paragraph::new(Line::from(spans)), area);
 }
 
-fn wrap_spans(spans: &[(Style, String)], width: usize) -> Vec<Vec<Span<'static>>> {
-    if width == 0 {
-        return vec![Vec::new()];
-    }
-    let mut lines: Vec<Vec<Span<'static>>> = vec![Vec::new()];
-    let mut cur_width = 0usize;
-
-    for (span_idx, (style, text)) in spans.iter().enumerate() {
-        let mut remaining = text.as_str();
-        while !remaining.is_empty() {
-            let available = width.saturating_sub(cur_width);
-            if available == 0 {
-                lines.push(Vec::new());
-                cur_width = 0;
+/// Split a highlighted line into screen rows as placed by `layout`, with
+/// tabs drawn as spaces up to the next tab stop. Without wrapping only the
+/// `width` cells from `left` on are kept, and clusters cut by either edge
+/// are drawn as spaces.
+fn place_spans(
+    spans: &[(Style, String)],
+    layout: &LineLayout,
+    left: usize,
+    width: usize,
+    wrap: WrapMode,
+) -> Vec<Vec<Span<'static>>> {
+    let window = if wrap == WrapMode::Off {
+        left..left + width
+    } else {
+        0..usize::MAX
+    };
+    let mut rows: Vec<Vec<Span<'static>>> = vec![Vec::new(); layout.rows()];
+    let mut placed = layout.placed.iter().peekable();
+    let mut offset = 0;
+    for (style, text) in spans {
+        let end = offset + text.len();
+        while let Some(place) = placed.next_if(|p| p.byte < end) {
+            let next = placed.peek().map_or(end.min(layout.bytes()), |p| p.byte.min(end));
+            let grapheme = &text[place.byte - offset..next - offset];
+            let from = place.col.max(window.start);
+            let to = (place.col + place.cells).min(window.end);
+            let piece = if place.cells == 0 {
+                // Zero-width clusters show where they are; hanging spaces do not
+                if grapheme.trim().is_empty() || !window.contains(&place.col) {
+                    continue;
+                }
+                grapheme.to_string()
+            } else if from >= to {
                 continue;
-            }
-
-            let (take, rest) = split_at_char_count(remaining, available);
-            if !take.is_empty() {
-                lines
-                    .last_mut()
-                    .unwrap()
-                    .push(Span::styled(take.to_string(), *style));
-                cur_width += take.chars().count();
-            }
-
-            remaining = rest;
-            if cur_width >= width && !remaining.is_empty() {
-                lines.push(Vec::new());
-                cur_width = 0;
+            } else if grapheme == "\t" || to - from < place.cells {
+                " ".repeat(to - from)
+            } else {
+                grapheme.to_string()
+            };
+            let line = &mut rows[place.row];
+            match line.last_mut() {
+                Some(span) if span.style == *style => span.content.to_mut().push_str(&piece),
+                _ => line.push(Span::styled(piece, *style)),
             }
         }
-
-        if cur_width >= width && span_idx + 1 < spans.len() {
-            lines.push(Vec::new());
-            cur_width = 0;
-        }
-    }
-
-    if lines.is_empty() {
-        lines.push(Vec::new());
-    }
-    lines
-}
-
-fn split_at_char_count(s: &str, count: usize) -> (&str, &str) {
-    if count == 0 {
-        return ("", s);
-    }
-    let mut chars = s.char_indices();
-    let mut end = s.len();
-    let mut seen = 0usize;
-    while let Some((idx, _)) = chars.next() {
-        if seen == count {
-            end = idx;
-            break;
-        }
-        seen += 1;
-    }
-    if seen < count {
-        return (s, "");
+        offset = end;
     }
-    (&s[..end], &s[end..])
+    rows
 }
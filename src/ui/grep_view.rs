@@ -0,0 +1,198 @@
+use super::{centered_rect, diff_line};
+use crate::app::{App, Mode};
+use crate::keymap::Action;
+use crate::grep::{Grep, GrepMatch};
+use crate::theme::Palette;
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph},
+};
+use unicode_width::UnicodeWidthStr;
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let replacing = app.grep.replacement.is_some();
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(1),                                 // Results
+            Constraint::Length(3),                              // Pattern input
+            Constraint::Length(if replacing { 3 } else { 0 }), // Replacement input
+            Constraint::Length(1),                              // Status bar
+        ])
+        .split(frame.area());
+
+    // Store list area for mouse click handling
+    app.list_area = chunks[0];
+
+    let grep = &app.grep;
+    let list_height = chunks[0].height.saturating_sub(2) as usize;
+    let scroll_offset = (grep.selected + 1).saturating_sub(list_height);
+
+    let items: Vec<ListItem> = grep
+        .results
+        .iter()
+        .enumerate()
+        .skip(scroll_offset)
+        .take(list_height)
+        .map(|(i, m)| ListItem::new(result_line(grep, &app.palette, i, m)))
+        .collect();
+
+    let title = format!(" Grep: {} ", app.search.root.to_string_lossy());
+    let list = List::new(items).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(title)
+            .title_style(Style::default().fg(app.palette.accent)),
+    );
+    frame.render_widget(list, chunks[0]);
+
+    let count = if let Some(ref error) = grep.error {
+        error.lines().last().unwrap_or_default().to_string()
+    } else if grep.searching {
+        format!("searching... {} matches", grep.results.len())
+    } else if grep.truncated {
+        format!("first {} matches", grep.results.len())
+    } else if replacing {
+        format!(
+            "{} of {} selected",
+            grep.results.len() - grep.excluded.len(),
+            grep.results.len()
+        )
+    } else {
+        format!("{} matches", grep.results.len())
+    };
+    let input_title = if grep.regex { " Grep (regex) " } else { " Grep (literal) " };
+    let input = Paragraph::new(format!(" {}", grep.query))
+        .style(Style::default().fg(app.palette.foreground))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(input_title)
+                .title_style(app.palette.title())
+                .title_bottom(Line::from(count).right_aligned()),
+        );
+    frame.render_widget(input, chunks[1]);
+
+    if let Some(ref replacement) = grep.replacement {
+        let title = if grep.regex { " Replace ($1 for groups) " } else { " Replace " };
+        let input = Paragraph::new(format!(" {}", replacement))
+            .style(Style::default().fg(app.palette.foreground))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(title)
+                    .title_style(app.palette.title()),
+            );
+        frame.render_widget(input, chunks[2]);
+    }
+
+    let (input_area, input_len) = match grep.replacement {
+        Some(ref replacement) if grep.replace_focus => (chunks[2], replacement.width()),
+        _ => (chunks[1], grep.query.width()),
+    };
+    frame.set_cursor_position(Position::new(
+        input_area.x + input_len as u16 + 2,
+        input_area.y + 1,
+    ));
+
+    let status_text = if let Some(ref msg) = app.status_message {
+        format!(" {}", msg)
+    } else if replacing {
+        let keys = app.config.keys.hints(
+            Mode::Grep,
+            &[
+                (&[Action::ToggleHit], "toggle hit"),
+                (&[Action::PreviewReplace], "preview file"),
+                (&[Action::ReplaceAll], "replace all"),
+                (&[Action::ToggleReplace], "cancel"),
+            ],
+        );
+        format!(" Tab: pattern/replacement | {}", keys)
+    } else {
+        let keys = app.config.keys.hints(
+            Mode::Grep,
+            &[
+                (&[Action::ToggleRegex], "literal/regex"),
+                (&[Action::ToggleReplace], "replace"),
+                (&[Action::BufferPicker], "buffers"),
+                (&[Action::CommandPalette], "commands"),
+                (&[Action::Back], "back"),
+            ],
+        );
+        format!(" Enter: open | {}", keys)
+    };
+    let status = Paragraph::new(status_text).style(app.palette.status());
+    frame.render_widget(status, chunks[3]);
+
+    draw_preview(frame, grep, &app.palette);
+}
+
+/// `path:line:col: text` with the matched part highlighted, or in replace
+/// mode struck through and followed by its replacement.
+fn result_line(grep: &Grep, palette: &Palette, idx: usize, m: &GrepMatch) -> Line<'static> {
+    let selected = idx == grep.selected;
+    let base = if selected {
+        palette.selected()
+    } else {
+        Style::default()
+    };
+    let mut spans = Vec::new();
+    if grep.replacement.is_some() {
+        let mark = if grep.excluded.contains(&idx) { "[ ] " } else { "[x] " };
+        spans.push(Span::styled(mark, base));
+    }
+    let location = format!("{}:{}:{}: ", m.path.to_string_lossy(), m.line + 1, m.column + 1);
+    spans.push(Span::styled(
+        location,
+        base.fg(if selected { palette.selection_fg } else { palette.accent }),
+    ));
+
+    // Leading indentation only wastes room in the list
+    let indent = (m.text.len() - m.text.trim_start().len()).min(m.range.start);
+    let start = m.range.start;
+    spans.push(Span::styled(m.text[indent..start].to_string(), base));
+    match grep.replacement_for(m) {
+        Some(with) if !grep.excluded.contains(&idx) => {
+            spans.push(Span::styled(
+                m.text[start..m.range.end].to_string(),
+                base.fg(palette.removed).add_modifier(Modifier::CROSSED_OUT),
+            ));
+            spans.push(Span::styled(
+                with,
+                base.fg(palette.added).add_modifier(Modifier::BOLD),
+            ));
+        }
+        _ => spans.push(Span::styled(
+            m.text[start..m.range.end].to_string(),
+            base.fg(palette.warning).add_modifier(Modifier::BOLD | Modifier::REVERSED),
+        )),
+    }
+    spans.push(Span::styled(m.text[m.range.end..].to_string(), base));
+    Line::from(spans)
+}
+
+fn draw_preview(frame: &mut Frame, grep: &Grep, palette: &Palette) {
+    let Some(ref preview) = grep.preview else {
+        return;
+    };
+
+    let area = centered_rect(frame.area(), 80, 80);
+    frame.render_widget(Clear, area);
+
+    let height = area.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = preview
+        .diff
+        .iter()
+        .skip(preview.scroll)
+        .take(height)
+        .map(|line| diff_line(line, palette))
+        .collect();
+    let popup = Paragraph::new(lines).style(palette.base()).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" Replace in {} ", preview.path.display()))
+            .title_style(palette.title())
+            .title_bottom(Line::from(" ↑/↓: scroll | any other key: close ").right_aligned()),
+    );
+    frame.render_widget(popup, area);
+}
@@ -0,0 +1,128 @@
+use crate::app::App;
+use crate::merge::{Choice, Resolution};
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, Paragraph},
+};
+
+/// One screen row across the three columns.
+struct Row<'a> {
+    cells: [Option<&'a str>; 3],
+    hunk: usize,
+    resolution: Resolution,
+}
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let Some(state) = app.active_buffer().and_then(|b| b.merge.as_ref()) else {
+        return;
+    };
+    let palette = &app.palette;
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(1),    // Base | Ours | Theirs
+            Constraint::Length(1), // Status bar
+        ])
+        .split(frame.area());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Ratio(1, 3); 3])
+        .split(chunks[0]);
+
+    let conflicts = state.merge.conflicts();
+    let selected_hunk = conflicts.get(state.selected).copied();
+
+    let mut rows: Vec<Row> = Vec::new();
+    let mut selected_row = 0;
+    for (hunk_idx, hunk) in state.merge.hunks.iter().enumerate() {
+        if Some(hunk_idx) == selected_hunk {
+            selected_row = rows.len();
+        }
+        let height = hunk.base.len().max(hunk.ours.len()).max(hunk.theirs.len());
+        for i in 0..height {
+            rows.push(Row {
+                cells: [
+                    hunk.base.get(i).map(String::as_str),
+                    hunk.ours.get(i).map(String::as_str),
+                    hunk.theirs.get(i).map(String::as_str),
+                ],
+                hunk: hunk_idx,
+                resolution: hunk.resolution,
+            });
+        }
+        // Keep empty conflicting hunks (pure deletions on both sides) visible
+        if height == 0 && hunk.is_conflict() {
+            rows.push(Row {
+                cells: [None, None, None],
+                hunk: hunk_idx,
+                resolution: hunk.resolution,
+            });
+        }
+    }
+
+    // Keep the selected conflict a third of the way down the view
+    let view_height = chunks[0].height.saturating_sub(2) as usize;
+    let scroll = selected_row.saturating_sub(view_height / 3);
+
+    let titles = [" Base (last loaded) ", " Ours (buffer) ", " Theirs (disk) "];
+    let chosen_column = |resolution: Resolution| match resolution {
+        Resolution::Conflict(Some(Choice::Base)) => Some(0),
+        Resolution::Conflict(Some(Choice::Ours)) => Some(1),
+        Resolution::Conflict(Some(Choice::Theirs)) => Some(2),
+        _ => None,
+    };
+
+    for (col, area) in columns.iter().enumerate() {
+        let lines: Vec<Line> = rows
+            .iter()
+            .skip(scroll)
+            .take(view_height)
+            .map(|row| {
+                let marker = if Some(row.hunk) == selected_hunk { "▶" } else { " " };
+                let text = row.cells[col].unwrap_or("");
+                let style = match row.resolution {
+                    Resolution::Unchanged => Style::default(),
+                    Resolution::Auto => Style::default().fg(palette.accent),
+                    Resolution::Conflict(None) => Style::default().bg(palette.removed).fg(palette.accent_fg),
+                    Resolution::Conflict(Some(Choice::Both)) if col > 0 => {
+                        Style::default().bg(palette.added).fg(palette.accent_fg)
+                    }
+                    Resolution::Conflict(choice) => {
+                        if chosen_column(Resolution::Conflict(choice)) == Some(col) {
+                            Style::default().bg(palette.added).fg(palette.accent_fg)
+                        } else {
+                            Style::default().fg(palette.gutter)
+                        }
+                    }
+                };
+                Line::from(vec![
+                    Span::styled(marker, Style::default().fg(palette.warning)),
+                    Span::styled(text.to_string(), style),
+                ])
+            })
+            .collect();
+
+        let widget = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(titles[col])
+                .title_style(palette.title()),
+        );
+        frame.render_widget(widget, *area);
+    }
+
+    let hints = "n/p: next/prev | o/t/b/a: ours/theirs/both/base | Enter: apply | Esc: back";
+    let status_text = match app.status_message {
+        Some(ref msg) => format!(" {}  |  {}", msg, hints),
+        None => format!(
+            " Conflict {}/{} ({} unresolved)  |  {}",
+            (state.selected + 1).min(conflicts.len()),
+            conflicts.len(),
+            state.merge.unresolved(),
+            hints
+        ),
+    };
+    let status = Paragraph::new(status_text).style(palette.alert());
+    frame.render_widget(status, chunks[1]);
+}
@@ -1,12 +1,62 @@
 mod editor_view;
+mod grep_view;
+mod merge_view;
+mod picker_view;
+mod quit_view;
+mod recovery_view;
 mod search_view;
 
 use crate::app::{App, Mode};
-use ratatui::prelude::*;
+use crate::swap::DiffLine;
+use crate::theme::Palette;
+use ratatui::{prelude::*, widgets::Block};
 
 pub fn draw(frame: &mut Frame, app: &mut App) {
+    // Everything is drawn over the theme's background
+    frame.render_widget(Block::default().style(app.palette.base()), frame.area());
     match app.mode {
         Mode::Search => search_view::draw(frame, app),
+        Mode::Grep => grep_view::draw(frame, app),
         Mode::Edit => editor_view::draw(frame, app),
+        Mode::Merge => merge_view::draw(frame, app),
+    }
+    if app.picker.is_some() {
+        picker_view::draw(frame, app);
+    }
+    if app.recovery.is_some() {
+        recovery_view::draw(frame, app);
+    }
+    if app.quit_prompt {
+        quit_view::draw(frame, app);
+    }
+}
+
+/// A rect of the given percentage size centred in `area`, for popups.
+fn centered_rect(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ])
+        .split(area);
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ])
+        .split(vertical[1])[1]
+}
+
+/// A diff line coloured as removed / added / context.
+fn diff_line(line: &DiffLine, palette: &Palette) -> Line<'static> {
+    match line {
+        DiffLine::Context(text) => Line::from(format!("  {}", text)),
+        DiffLine::Removed(text) => Line::styled(format!("- {}", text), Style::default().fg(palette.removed)),
+        DiffLine::Added(text) => Line::styled(format!("+ {}", text), Style::default().fg(palette.added)),
+        DiffLine::Separator => Line::styled("  ⋯", Style::default().fg(palette.gutter)),
     }
 }
@@ -0,0 +1,72 @@
+use super::centered_rect;
+use crate::app::App;
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph},
+};
+use unicode_width::UnicodeWidthStr;
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let Some(ref picker) = app.picker else {
+        return;
+    };
+
+    let area = centered_rect(frame.area(), 60, 60);
+    frame.render_widget(Clear, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3), // Query input
+            Constraint::Min(1),    // Matches
+        ])
+        .split(area);
+
+    let input = Paragraph::new(format!(" {}", picker.query)).style(app.palette.base()).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(picker.title.as_str())
+            .title_style(app.palette.title())
+            .title_bottom(Line::from(format!("{}/{}", picker.matches.len(), picker.items.len())).right_aligned()),
+    );
+    frame.render_widget(input, chunks[0]);
+
+    let list_height = chunks[1].height.saturating_sub(2) as usize;
+    let scroll_offset = if picker.selected >= list_height {
+        picker.selected - list_height + 1
+    } else {
+        0
+    };
+    let items: Vec<ListItem> = picker
+        .matches
+        .iter()
+        .enumerate()
+        .skip(scroll_offset)
+        .take(list_height)
+        .map(|(i, &item)| {
+            let style = if i == picker.selected {
+                app.palette.selected().add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            let mut spans = vec![Span::raw(picker.items[item].as_str())];
+            if let Some(hint) = picker.hints.get(item) {
+                // Right-aligned inside the borders
+                let used = picker.items[item].width() + hint.width();
+                let gap = (chunks[1].width as usize).saturating_sub(used + 3);
+                spans.push(Span::raw(" ".repeat(gap.max(1))));
+                spans.push(Span::styled(hint.as_str(), Style::default().fg(app.palette.gutter)));
+            }
+            ListItem::new(Line::from(spans)).style(style)
+        })
+        .collect();
+    let list = List::new(items)
+        .style(app.palette.base())
+        .block(Block::default().borders(Borders::ALL));
+    frame.render_widget(list, chunks[1]);
+
+    frame.set_cursor_position(Position::new(
+        chunks[0].x + picker.query.width() as u16 + 2,
+        chunks[0].y + 1,
+    ));
+}
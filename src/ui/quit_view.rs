@@ -0,0 +1,27 @@
+use super::centered_rect;
+use crate::app::App;
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, Clear, Paragraph},
+};
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let modified: Vec<Line> = app
+        .buffers
+        .iter()
+        .filter(|b| b.editor.is_modified())
+        .map(|b| Line::from(format!("  {}", app.buffer_name(b))))
+        .collect();
+
+    let area = centered_rect(frame.area(), 50, 40);
+    frame.render_widget(Clear, area);
+
+    let popup = Paragraph::new(modified).style(app.palette.base()).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" Unsaved changes ")
+            .title_style(app.palette.title())
+            .title_bottom(Line::from(" s: save all | d: discard all | Esc: cancel ").right_aligned()),
+    );
+    frame.render_widget(popup, area);
+}
@@ -0,0 +1,40 @@
+use super::{centered_rect, diff_line};
+use crate::app::App;
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, Clear, Paragraph},
+};
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let Some(ref recovery) = app.recovery else {
+        return;
+    };
+
+    let area = centered_rect(frame.area(), 80, 80);
+    frame.render_widget(Clear, area);
+
+    let height = area.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = recovery
+        .diff
+        .iter()
+        .skip(recovery.scroll)
+        .take(height)
+        .map(|line| diff_line(line, &app.palette))
+        .collect();
+
+    let title = match app.active_editor() {
+        Some(editor) => format!(" Unsaved edits found for {} ", editor.filename()),
+        None => " Unsaved edits found ".to_string(),
+    };
+    let popup = Paragraph::new(lines).style(app.palette.base()).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(title)
+            .title_style(app.palette.title())
+            .title_bottom(
+                Line::from(" r: recover | d: delete swap | Esc: keep swap, open disk version ")
+                    .right_aligned(),
+            ),
+    );
+    frame.render_widget(popup, area);
+}
@@ -1,8 +1,12 @@
-use crate::app::App;
+use crate::app::{App, CreateKind, Mode};
+use crate::keymap::Action;
+use crate::search::PathMatch;
 use ratatui::{
     prelude::*,
     widgets::{Block, Borders, List, ListItem, Paragraph},
 };
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 pub fn draw(frame: &mut Frame, app: &mut App) {
     let chunks = Layout::default()
@@ -31,21 +35,16 @@ pub fn draw(frame: &mut Frame, app: &mut App) {
         let match_count = app.search.match_count();
         let end = (scroll_offset + list_height).min(match_count);
         (scroll_offset..end)
-            .filter_map(|i| app.search.match_path_at(i).map(|(p, s)| (i, p, s)))
-            .map(|(i, path, score)| {
-                let path_str = path.to_string_lossy();
-                let content = format!("{} ({})", path_str, score);
-
-                let style = if i == app.selected_index {
-                    Style::default()
-                        .fg(Color::Black)
-                        .bg(Color::Cyan)
-                        .add_modifier(Modifier::BOLD)
+            .filter_map(|i| app.search.match_path_at(i).map(|found| (i, found)))
+            .map(|(i, found)| {
+                let selected = i == app.selected_index;
+                let style = if selected {
+                    app.palette.selected()
                 } else {
                     Style::default()
                 };
 
-                ListItem::new(content).style(style)
+                ListItem::new(match_line(app, &found, selected)).style(style)
             })
             .collect()
     } else {
@@ -73,12 +72,9 @@ pub fn draw(frame: &mut Frame, app: &mut App) {
                 };
 
                 let style = if i == app.selected_index {
-                    Style::default()
-                        .fg(Color::Black)
-                        .bg(Color::Cyan)
-                        .add_modifier(Modifier::BOLD)
+                    app.palette.selected().add_modifier(Modifier::BOLD)
                 } else if entry.is_dir {
-                    Style::default().fg(Color::Blue).add_modifier(Modifier::BOLD)
+                    Style::default().fg(app.palette.accent).add_modifier(Modifier::BOLD)
                 } else {
                     Style::default()
                 };
@@ -96,7 +92,7 @@ pub fn draw(frame: &mut Frame, app: &mut App) {
         Block::default()
             .borders(Borders::ALL)
             .title(title)
-            .title_style(Style::default().fg(Color::Cyan)),
+            .title_style(Style::default().fg(app.palette.accent)),
     );
     frame.render_widget(list, chunks[0]);
 
@@ -115,11 +111,11 @@ pub fn draw(frame: &mut Frame, app: &mut App) {
     };
 
     let (input_text, input_title, input_bottom) = if app.create_active {
-        (
-            app.create_input.as_str(),
-            format!(" New (in {}/) ", base_display),
-            "Enter: create | Esc: cancel".to_string(),
-        )
+        let (title, bottom) = match app.create_kind {
+            CreateKind::New => (format!(" New (in {}/) ", base_display), "Enter: create | Esc: cancel"),
+            CreateKind::SaveAs => (format!(" Save as (in {}/) ", base_display), "Enter: save | Esc: cancel"),
+        };
+        (app.create_input.as_str(), title, bottom.to_string())
     } else {
         (
             app.search_input.as_str(),
@@ -129,21 +125,21 @@ pub fn draw(frame: &mut Frame, app: &mut App) {
     };
 
     let input = Paragraph::new(format!(" {}", input_text))
-        .style(Style::default().fg(Color::White))
+        .style(Style::default().fg(app.palette.foreground))
         .block(
             Block::default()
                 .borders(Borders::ALL)
                 .title(input_title)
-                .title_style(Style::default().fg(Color::Yellow))
+                .title_style(app.palette.title())
                 .title_bottom(Line::from(input_bottom).right_aligned()),
         );
     frame.render_widget(input, chunks[1]);
 
     // Cursor position in input box
     let cursor_len = if app.create_active {
-        app.create_input.len()
+        app.create_input.width()
     } else {
-        app.search_input.len()
+        app.search_input.width()
     };
     frame.set_cursor_position(Position::new(
         chunks[1].x + cursor_len as u16 + 2,
@@ -156,14 +152,68 @@ pub fn draw(frame: &mut Frame, app: &mut App) {
     } else {
         "Hidden: OFF"
     };
-    let status_text = if app.create_active {
+    let status_text = if app.create_active && app.create_kind == CreateKind::SaveAs {
+        format!(" Save as in {}/ | Enter: save | Esc: cancel | {}", base_display, hidden_status)
+    } else if app.create_active {
         format!(" New in {}/ | Enter: create | Esc: cancel | {}", base_display, hidden_status)
     } else if let Some(ref msg) = app.status_message {
         format!(" {} | {}", msg, hidden_status)
     } else {
-        format!(" Tab: toggle hidden | Ctrl+N: new | {}", hidden_status)
+        let keys = app.config.keys.hints(
+            Mode::Search,
+            &[
+                (&[Action::ToggleHidden], "toggle hidden"),
+                (&[Action::NewFile], "new"),
+                (&[Action::Grep], "grep"),
+                (&[Action::BufferPicker], "buffers"),
+                (&[Action::ThemePicker], "theme"),
+                (&[Action::CommandPalette], "commands"),
+            ],
+        );
+        format!(" {} | {}", keys, hidden_status)
     };
-    let status = Paragraph::new(status_text)
-        .style(Style::default().bg(Color::DarkGray).fg(Color::White));
+    let status = Paragraph::new(status_text).style(app.palette.status());
     frame.render_widget(status, chunks[2]);
 }
+
+/// A matched path with the clusters the query matched picked out and the
+/// file name standing out from its directory.
+fn match_line(app: &App, found: &PathMatch, selected: bool) -> Line<'static> {
+    let path = found.path.to_string_lossy();
+    // The matcher indexes grapheme clusters, not chars
+    let name_len = found.path.file_name().map_or(0, |name| name.to_string_lossy().graphemes(true).count());
+    let name_start = path.graphemes(true).count() - name_len;
+    // The selection colour can be the accent, so matches on the selected
+    // row are underlined instead
+    let matched = if selected {
+        Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED)
+    } else {
+        Style::default().fg(app.palette.accent).add_modifier(Modifier::BOLD)
+    };
+    let dir = if selected {
+        Style::default()
+    } else {
+        Style::default().fg(app.palette.gutter)
+    };
+
+    let mut indices = found.indices.iter().peekable();
+    let mut spans: Vec<Span> = Vec::new();
+    for (idx, grapheme) in path.graphemes(true).enumerate() {
+        let mut style = if idx < name_start {
+            dir
+        } else {
+            Style::default().add_modifier(Modifier::BOLD)
+        };
+        if indices.next_if_eq(&&(idx as u32)).is_some() {
+            style = style.patch(matched);
+        }
+        match spans.last_mut() {
+            Some(span) if span.style == style => span.content.to_mut().push_str(grapheme),
+            _ => spans.push(Span::styled(grapheme.to_string(), style)),
+        }
+    }
+    if app.show_scores {
+        spans.push(Span::styled(format!(" ({})", found.score), dir));
+    }
+    Line::from(spans)
+}
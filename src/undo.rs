@@ -0,0 +1,403 @@
+use anyhow::Result;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::editor::simple_hash;
+use crate::paths;
+use crate::save::{self, BackupMode};
+
+/// Bump when the on-disk layout of `UndoFile` changes.
+const UNDO_FILE_VERSION: u32 = 2;
+
+/// Consecutive typing within this window is grouped into one undo step.
+const COALESCE_WINDOW: Duration = Duration::from_millis(1000);
+
+/// A reversible change: the text `old` starting at char `at` was replaced
+/// by `new`. Only the changed text is kept, so an edit costs the same
+/// wherever it is made and however long its line is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edit {
+    pub at: usize,
+    pub old: String,
+    pub new: String,
+}
+
+impl Edit {
+    /// Smallest edit turning `before` into `after`, or `None` if equal.
+    pub fn between(before: &Rope, after: &Rope) -> Option<Edit> {
+        let prefix = before
+            .chars()
+            .zip(after.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if prefix == before.len_chars() && prefix == after.len_chars() {
+            return None;
+        }
+        let max_suffix = before.len_chars().min(after.len_chars()) - prefix;
+        let mut before_rev = before.chars_at(before.len_chars());
+        let mut after_rev = after.chars_at(after.len_chars());
+        let mut suffix = 0;
+        while suffix < max_suffix {
+            match (before_rev.prev(), after_rev.prev()) {
+                (Some(a), Some(b)) if a == b => suffix += 1,
+                _ => break,
+            }
+        }
+
+        Some(Edit {
+            at: prefix,
+            old: before.slice(prefix..before.len_chars() - suffix).to_string(),
+            new: after.slice(prefix..after.len_chars() - suffix).to_string(),
+        })
+    }
+
+    pub fn apply(&self, text: &mut Rope) {
+        let at = self.at.min(text.len_chars());
+        let end = (at + self.old.chars().count()).min(text.len_chars());
+        text.remove(at..end);
+        text.insert(at, &self.new);
+    }
+
+    /// The edit that undoes this one.
+    pub fn inverse(&self) -> Edit {
+        Edit {
+            at: self.at,
+            old: self.new.clone(),
+            new: self.old.clone(),
+        }
+    }
+
+    /// Fold `next` into `self` when it inserts right where `self`'s text ends.
+    fn merge(&mut self, next: &Edit) -> bool {
+        if !next.old.is_empty() || next.at != self.at + self.new.chars().count() {
+            return false;
+        }
+        self.new.push_str(&next.new);
+        true
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Node {
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// Child most recently undone from; redo follows this branch.
+    last_child: Option<usize>,
+    edits: Vec<Edit>,
+    cursor_before: (usize, usize),
+    cursor_after: (usize, usize),
+}
+
+/// Branching undo history for one buffer.
+///
+/// Node 0 is the root (the state the history started from). Making an edit
+/// after an undo starts a new branch instead of discarding the old one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoTree {
+    nodes: Vec<Node>,
+    current: usize,
+    /// Hash of the buffer content at `current`, used to check the history
+    /// still belongs to the text it is attached to.
+    content_hash: u64,
+    #[serde(skip)]
+    last_record: Option<Instant>,
+}
+
+impl UndoTree {
+    pub fn new(content_hash: u64) -> Self {
+        Self {
+            nodes: vec![Node {
+                parent: None,
+                children: Vec::new(),
+                last_child: None,
+                edits: Vec::new(),
+                cursor_before: (0, 0),
+                cursor_after: (0, 0),
+            }],
+            current: 0,
+            content_hash,
+            last_record: None,
+        }
+    }
+
+    pub fn content_hash(&self) -> u64 {
+        self.content_hash
+    }
+
+    pub fn set_content_hash(&mut self, hash: u64) {
+        self.content_hash = hash;
+    }
+
+    /// Record `edit` as a new step. When `coalesce` is set and the previous
+    /// step was also coalescable typing ending where `edit` starts, the edit
+    /// is folded into it instead.
+    pub fn record(
+        &mut self,
+        edit: Edit,
+        cursor_before: (usize, usize),
+        cursor_after: (usize, usize),
+        coalesce: bool,
+    ) {
+        let recent = self
+            .last_record
+            .map(|t| t.elapsed() < COALESCE_WINDOW)
+            .unwrap_or(false);
+        self.last_record = if coalesce { Some(Instant::now()) } else { None };
+
+        if coalesce && recent && self.current != 0 {
+            let node = &mut self.nodes[self.current];
+            if node.children.is_empty() {
+                if let Some(last) = node.edits.last_mut() {
+                    if last.merge(&edit) {
+                        node.cursor_after = cursor_after;
+                        return;
+                    }
+                }
+            }
+        }
+
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            parent: Some(self.current),
+            children: Vec::new(),
+            last_child: None,
+            edits: vec![edit],
+            cursor_before,
+            cursor_after,
+        });
+        let parent = &mut self.nodes[self.current];
+        parent.children.push(id);
+        parent.last_child = Some(id);
+        self.current = id;
+    }
+
+    /// Step back one node. Returns the edits that revert it, in the order
+    /// to apply them, and the cursor to restore.
+    pub fn undo(&mut self) -> Option<(Vec<Edit>, (usize, usize))> {
+        let node = &self.nodes[self.current];
+        let parent = node.parent?;
+        let edits = node.edits.iter().rev().map(Edit::inverse).collect();
+        let cursor = node.cursor_before;
+        let undone = self.current;
+        self.nodes[parent].last_child = Some(undone);
+        self.current = parent;
+        self.last_record = None;
+        Some((edits, cursor))
+    }
+
+    /// Re-apply the most recently undone child of the current node. Returns
+    /// its edits and the cursor to restore.
+    pub fn redo(&mut self) -> Option<(Vec<Edit>, (usize, usize))> {
+        let node = &self.nodes[self.current];
+        let child = node.last_child.or_else(|| node.children.last().copied())?;
+        let child_node = &self.nodes[child];
+        let edits = child_node.edits.clone();
+        let cursor = child_node.cursor_after;
+        self.current = child;
+        self.last_record = None;
+        Some((edits, cursor))
+    }
+
+    /// Node the buffer is currently at.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Stop the next edit from being folded into the current step, e.g.
+    /// after a save so the saved state stays reachable.
+    pub fn seal(&mut self) {
+        self.last_record = None;
+    }
+
+    /// Number of steps that can be undone from the current state.
+    pub fn undo_depth(&self) -> usize {
+        let mut depth = 0;
+        let mut node = self.current;
+        while let Some(parent) = self.nodes[node].parent {
+            depth += 1;
+            node = parent;
+        }
+        depth
+    }
+
+    /// Number of steps along the redo branch from the current state.
+    pub fn redo_depth(&self) -> usize {
+        let mut depth = 0;
+        let mut node = self.current;
+        loop {
+            let n = &self.nodes[node];
+            match n.last_child.or_else(|| n.children.last().copied()) {
+                Some(child) => {
+                    depth += 1;
+                    node = child;
+                }
+                None => return depth,
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct UndoFile {
+    version: u32,
+    path: PathBuf,
+    tree: UndoTree,
+}
+
+fn undo_file_path(path: &Path) -> Option<PathBuf> {
+    let key = paths::state_key(path);
+    let name = format!("{:016x}.json", simple_hash(&key.to_string_lossy()));
+    paths::state_dir().map(|dir| dir.join("undo").join(name))
+}
+
+/// Load the persisted history for `path` if it was saved against content
+/// with hash `content_hash`.
+pub fn load(path: &Path, content_hash: u64) -> Option<UndoTree> {
+    let file = undo_file_path(path)?;
+    let data = fs::read(file).ok()?;
+    let stored: UndoFile = serde_json::from_slice(&data).ok()?;
+    if stored.version != UNDO_FILE_VERSION
+        || stored.path != paths::state_key(path)
+        || stored.tree.content_hash != content_hash
+    {
+        return None;
+    }
+    Some(stored.tree)
+}
+
+/// Persist `tree` for `path` under the state directory.
+pub fn store(path: &Path, tree: &UndoTree) -> Result<()> {
+    let Some(file) = undo_file_path(path) else {
+        return Ok(());
+    };
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let stored = UndoFile {
+        version: UNDO_FILE_VERSION,
+        path: paths::state_key(path),
+        tree: tree.clone(),
+    };
+    // A crash mid-write must not cost the whole history
+    save::write_file(&file, &serde_json::to_vec(&stored)?, BackupMode::None)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(at: usize, text: &str) -> Edit {
+        Edit {
+            at,
+            old: String::new(),
+            new: text.to_string(),
+        }
+    }
+
+    /// Apply `edits` to `text` in order.
+    fn apply(text: &mut Rope, edits: &[Edit]) {
+        for edit in edits {
+            edit.apply(text);
+        }
+    }
+
+    #[test]
+    fn between_finds_the_changed_range() {
+        let before = Rope::from_str("one\ntwo\nthree");
+        let after = Rope::from_str("one\ntoo\nthree");
+        let edit = Edit::between(&before, &after).unwrap();
+        assert_eq!(edit, Edit { at: 5, old: "w".into(), new: "o".into() });
+
+        let mut text = before.clone();
+        edit.apply(&mut text);
+        assert_eq!(text, after);
+        edit.inverse().apply(&mut text);
+        assert_eq!(text, before);
+        assert_eq!(Edit::between(&before, &before), None);
+    }
+
+    #[test]
+    fn between_handles_repeats_at_the_edges() {
+        let before = Rope::from_str("aaa");
+        let after = Rope::from_str("aaaaa");
+        let mut text = before.clone();
+        Edit::between(&before, &after).unwrap().apply(&mut text);
+        assert_eq!(text, after);
+    }
+
+    #[test]
+    fn undo_and_redo_step_through_history() {
+        let mut text = Rope::from_str("ab");
+        let mut tree = UndoTree::new(0);
+        for (edit, col) in [(insert(2, "\nc"), 1), (insert(4, "d"), 2)] {
+            edit.apply(&mut text);
+            tree.record(edit, (0, 0), (1, col), false);
+        }
+        assert_eq!(text, "ab\ncd");
+        assert_eq!((tree.undo_depth(), tree.redo_depth()), (2, 0));
+
+        let (edits, cursor) = tree.undo().unwrap();
+        apply(&mut text, &edits);
+        assert_eq!((text.to_string().as_str(), cursor), ("ab\nc", (0, 0)));
+        let (edits, _) = tree.undo().unwrap();
+        apply(&mut text, &edits);
+        assert_eq!(text, "ab");
+        assert!(tree.undo().is_none());
+        assert_eq!((tree.undo_depth(), tree.redo_depth()), (0, 2));
+
+        let (edits, cursor) = tree.redo().unwrap();
+        apply(&mut text, &edits);
+        assert_eq!((text.to_string().as_str(), cursor), ("ab\nc", (1, 1)));
+    }
+
+    #[test]
+    fn editing_after_undo_starts_a_branch() {
+        let mut text = Rope::from_str("");
+        let mut tree = UndoTree::new(0);
+        for edit in [insert(0, "a"), insert(1, "b")] {
+            edit.apply(&mut text);
+            tree.record(edit, (0, 0), (0, 0), false);
+        }
+        let (edits, _) = tree.undo().unwrap();
+        apply(&mut text, &edits);
+        let edit = insert(1, "c");
+        edit.apply(&mut text);
+        tree.record(edit, (0, 0), (0, 0), false);
+        assert_eq!(text, "ac");
+
+        // The "b" branch is still there: back to "a", then redo follows the
+        // branch most recently undone from
+        let (edits, _) = tree.undo().unwrap();
+        apply(&mut text, &edits);
+        let (edits, _) = tree.redo().unwrap();
+        apply(&mut text, &edits);
+        assert_eq!(text, "ac");
+        assert_eq!(tree.nodes[1].children.len(), 2);
+    }
+
+    #[test]
+    fn typing_is_coalesced_into_one_step() {
+        let mut text = Rope::from_str("");
+        let mut tree = UndoTree::new(0);
+        for (at, c) in "word".char_indices() {
+            let edit = insert(at, &c.to_string());
+            edit.apply(&mut text);
+            tree.record(edit, (0, at), (0, at + 1), true);
+        }
+        // Not typed on from the end of the run, so not folded in
+        let edit = insert(0, "x");
+        edit.apply(&mut text);
+        tree.record(edit, (0, 0), (0, 1), true);
+        assert_eq!(text, "xword");
+        assert_eq!(tree.undo_depth(), 2);
+
+        tree.undo();
+        let (edits, cursor) = tree.undo().unwrap();
+        assert_eq!(edits, [Edit { at: 0, old: "word".into(), new: String::new() }]);
+        assert_eq!(cursor, (0, 0));
+    }
+}
@@ -0,0 +1,431 @@
+use serde::Deserialize;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::editor::Editor;
+
+/// What part of a buffer a pane shows: the first visible line and how many
+/// of its wrapped rows are scrolled past. Kept between frames so drawing
+/// only has to look at the lines on screen.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Viewport {
+    pub top: usize,
+    /// Wrapped rows of `top` above the view
+    pub wrap: usize,
+    /// Cells scrolled past on the left, when not wrapping
+    pub left: usize,
+    /// Text columns and rows as of the last frame
+    pub width: usize,
+    pub height: usize,
+    mode: WrapMode,
+    /// Cursor and text revision seen last frame. The view only jumps to
+    /// the cursor when one of them changes, so scrolling away sticks.
+    followed: Option<((usize, usize), u64)>,
+}
+
+impl Viewport {
+    /// Resize to the pane and scroll to the cursor if it moved, or if the
+    /// text changed while `focused`, since the last frame.
+    pub fn update(&mut self, editor: &Editor, cursor: (usize, usize), focused: bool, width: usize, height: usize) {
+        let resized = (width, height, editor.wrap) != (self.width, self.height, self.mode);
+        self.width = width;
+        self.height = height;
+        self.mode = editor.wrap;
+        if self.mode != WrapMode::Off {
+            self.left = 0;
+        }
+        // Lines may have been removed through another pane
+        self.clamp(editor);
+
+        let revision = editor.revision();
+        let follow = match self.followed {
+            Some((seen, seen_revision)) => seen != cursor || (focused && seen_revision != revision),
+            None => true,
+        };
+        self.followed = Some((cursor, revision));
+        if follow || resized {
+            self.reveal(editor, cursor);
+        }
+    }
+
+    fn clamp(&mut self, editor: &Editor) {
+        self.top = self.top.min(editor.line_count() - 1);
+        self.wrap = self.wrap.min(self.rows_of(editor, self.top, self.wrap + 1) - 1);
+    }
+
+    /// Line `idx` laid out for this view, as far as `stop`.
+    pub fn layout(&self, editor: &Editor, idx: usize, stop: Stop) -> LineLayout {
+        LineLayout::new(&editor.line(idx), self.width, editor.tab_width, self.mode, stop)
+    }
+
+    /// Screen rows taken by line `idx`, counting no further than `cap`.
+    fn rows_of(&self, editor: &Editor, idx: usize, cap: usize) -> usize {
+        if self.mode == WrapMode::Off {
+            return 1;
+        }
+        self.layout(editor, idx, Stop::Cell(cap.max(1), 0)).rows()
+    }
+
+    fn cursor_wrap(&self, editor: &Editor, (row, col): (usize, usize)) -> usize {
+        if self.mode == WrapMode::Off {
+            return 0;
+        }
+        self.layout(editor, row, Stop::Char(col)).cursor_cell(col).0
+    }
+
+    /// Row of the view the cursor is on, if it is on screen.
+    pub fn cursor_row(&self, editor: &Editor, (row, col): (usize, usize)) -> Option<usize> {
+        let cursor_wrap = self.cursor_wrap(editor, (row, col));
+        // Every line takes at least one row, so past `height` lines is off screen
+        if (row, cursor_wrap) < (self.top, self.wrap) || row - self.top >= self.height {
+            return None;
+        }
+        // A line reaching past the bottom pushes the cursor off screen
+        // whatever its full height, so none is laid out further than that
+        let cap = self.wrap + self.height + 1;
+        let above: usize = (self.top..row).map(|idx| self.rows_of(editor, idx, cap)).sum();
+        let screen_row = above + cursor_wrap - self.wrap;
+        (screen_row < self.height).then_some(screen_row)
+    }
+
+    /// Scroll as little as possible to bring the cursor on screen.
+    fn reveal(&mut self, editor: &Editor, cursor: (usize, usize)) {
+        if self.mode == WrapMode::Off {
+            let cell = self.layout(editor, cursor.0, Stop::Char(cursor.1)).cursor_cell(cursor.1).1;
+            if cell < self.left {
+                self.left = cell;
+            } else if cell >= self.left + self.width {
+                self.left = cell + 1 - self.width;
+            }
+        }
+        if self.cursor_row(editor, cursor).is_some() {
+            return;
+        }
+        let row = cursor.0;
+        let cursor_wrap = self.cursor_wrap(editor, cursor);
+        if (row, cursor_wrap) < (self.top, self.wrap) {
+            self.top = row;
+            self.wrap = cursor_wrap;
+            return;
+        }
+        // Below the view: put the cursor on the bottom row
+        self.top = row;
+        self.wrap = cursor_wrap;
+        self.scroll(editor, -(self.height.saturating_sub(1) as isize));
+    }
+
+    /// Move the view sideways by `delta` cells when not wrapping.
+    pub fn scroll_horizontal(&mut self, delta: isize) {
+        if self.mode == WrapMode::Off {
+            self.left = self.left.saturating_add_signed(delta);
+        }
+    }
+
+    /// Move the view by `delta` screen rows without touching the cursor.
+    pub fn scroll(&mut self, editor: &Editor, delta: isize) {
+        for _ in 0..delta.unsigned_abs() {
+            if delta < 0 {
+                if self.wrap > 0 {
+                    self.wrap -= 1;
+                } else if self.top > 0 {
+                    self.top -= 1;
+                    self.wrap = self.rows_of(editor, self.top, usize::MAX) - 1;
+                } else {
+                    break;
+                }
+            } else if self.wrap + 1 < self.rows_of(editor, self.top, self.wrap + 2) {
+                self.wrap += 1;
+            } else if self.top + 1 < editor.line_count() {
+                self.top += 1;
+                self.wrap = 0;
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// How lines longer than the pane are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapMode {
+    /// Break at spaces, falling back to any cluster for overlong words
+    #[default]
+    Word,
+    /// Break at whichever cluster reaches the edge
+    Char,
+    /// One row per line; the view scrolls sideways instead
+    Off,
+}
+
+impl WrapMode {
+    pub fn label(self) -> &'static str {
+        match self {
+            WrapMode::Word => "word wrap",
+            WrapMode::Char => "char wrap",
+            WrapMode::Off => "no wrap",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            WrapMode::Word => WrapMode::Off,
+            WrapMode::Off => WrapMode::Char,
+            WrapMode::Char => WrapMode::Word,
+        }
+    }
+}
+
+/// How far to lay out a line. Everything before the stop is placed, so
+/// views of long lines only pay for the part they show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// Once the cluster holding char `col` is placed
+    Char(usize),
+    /// Before the first cluster that would be drawn at or past this row
+    /// and cell
+    Cell(usize, usize),
+}
+
+/// Where one grapheme cluster of a line is drawn.
+#[derive(Debug, Clone, Copy)]
+pub struct Placed {
+    /// Byte and char offset of the cluster in the line
+    pub byte: usize,
+    pub chars: usize,
+    pub row: usize,
+    pub col: usize,
+    /// Cells it is drawn in; tabs are cut short at the end of a row and
+    /// spaces hanging past it take none
+    pub cells: usize,
+}
+
+/// A line's grapheme clusters laid out on rows of `width` terminal cells.
+/// Wide characters that do not fit move to the next row and tabs expand
+/// to the next multiple of `tab_width`.
+pub struct LineLayout {
+    pub placed: Vec<Placed>,
+    /// Where a cluster appended to the laid out part would go
+    end: (usize, usize),
+    chars: usize,
+    bytes: usize,
+}
+
+impl LineLayout {
+    pub fn new(line: &str, width: usize, tab_width: usize, wrap: WrapMode, stop: Stop) -> Self {
+        let width = if wrap == WrapMode::Off { usize::MAX } else { width.max(1) };
+        let tab_width = tab_width.max(1);
+        let mut placed = Vec::new();
+        let (mut row, mut col, mut line_col, mut chars) = (0, 0, 0, 0);
+        let mut after_blank = true;
+        let mut bytes = line.len();
+        let mut stopped = false;
+
+        for (byte, grapheme) in line.grapheme_indices(true) {
+            if matches!(stop, Stop::Char(stop_col) if chars > stop_col) {
+                bytes = byte;
+                stopped = true;
+                break;
+            }
+            let tab = grapheme == "\t";
+            let blank = is_blank(grapheme);
+            let cells = if tab {
+                tab_width - line_col % tab_width
+            } else {
+                grapheme.width()
+            };
+            let overflows = cells > 0 && (col >= width || (!tab && col > 0 && col + cells > width));
+            let wraps = if wrap != WrapMode::Word {
+                overflows
+            } else if blank {
+                // Spaces at the edge hang off it instead of opening a row
+                false
+            } else if col > 0 && after_blank {
+                // Start of a word: move it down whole if it fits on a row.
+                // Only measured as far as telling whether it does.
+                let mut word = 0;
+                for g in line[byte..].graphemes(true).take_while(|g| !is_blank(g)) {
+                    word += g.width();
+                    if word > width {
+                        break;
+                    }
+                }
+                (col + word > width && word <= width) || overflows
+            } else {
+                overflows
+            };
+            let at = if wraps { (row + 1, 0) } else { (row, col) };
+            if matches!(stop, Stop::Cell(stop_row, stop_col) if at >= (stop_row, stop_col)) {
+                bytes = byte;
+                stopped = true;
+                break;
+            }
+            (row, col) = at;
+            let shown = cells.min(width.saturating_sub(col));
+            placed.push(Placed {
+                byte,
+                chars,
+                row,
+                col,
+                cells: shown,
+            });
+            col += shown;
+            line_col += cells;
+            chars += grapheme.chars().count();
+            after_blank = blank;
+        }
+        // A line filling its last row ends on the next one, where the
+        // cursor goes after it
+        let end = if !stopped && col > 0 && col >= width {
+            (row + 1, 0)
+        } else {
+            (row, col)
+        };
+        Self {
+            placed,
+            end,
+            chars,
+            bytes,
+        }
+    }
+
+    /// Display cell of char `col` on the unwrapped line.
+    pub fn cell_at_col(line: &str, col: usize, tab_width: usize) -> usize {
+        Self::new(line, usize::MAX, tab_width, WrapMode::Off, Stop::Char(col))
+            .cursor_cell(col)
+            .1
+    }
+
+    /// Char column of the cluster covering cell `cell` of the unwrapped
+    /// line, or the line end if it is shorter.
+    pub fn col_at_cell(line: &str, cell: usize, tab_width: usize) -> usize {
+        let layout = Self::new(line, usize::MAX, tab_width, WrapMode::Off, Stop::Cell(0, cell + 1));
+        layout
+            .placed
+            .iter()
+            .find(|p| p.col + p.cells > cell)
+            .map_or(layout.chars, |p| p.chars)
+    }
+
+    /// Rows of the laid out part.
+    pub fn rows(&self) -> usize {
+        self.end.0 + 1
+    }
+
+    /// Cells the laid out part takes on its last row; its whole width if
+    /// unwrapped.
+    pub fn width(&self) -> usize {
+        self.end.1
+    }
+
+    /// Bytes of the line laid out: all of them unless it stopped early.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Row and cell of the cursor at char `col`, which must be laid out. A
+    /// cursor at the end of a line that fills its last row starts the next
+    /// row rather than sitting on the border.
+    pub fn cursor_cell(&self, col: usize) -> (usize, usize) {
+        if col >= self.chars {
+            return self.end;
+        }
+        // Inside a cluster counts as on it
+        let idx = self.placed.partition_point(|p| p.chars <= col) - 1;
+        (self.placed[idx].row, self.placed[idx].col)
+    }
+}
+
+fn is_blank(grapheme: &str) -> bool {
+    grapheme.chars().all(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `line` laid out in full on rows of `width` cells.
+    fn layout(line: &str, width: usize, wrap: WrapMode) -> LineLayout {
+        LineLayout::new(line, width, 4, wrap, Stop::Cell(usize::MAX, 0))
+    }
+
+    /// (row, col, cells) of each cluster.
+    fn cells(layout: &LineLayout) -> Vec<(usize, usize, usize)> {
+        layout.placed.iter().map(|p| (p.row, p.col, p.cells)).collect()
+    }
+
+    #[test]
+    fn wide_characters_move_down_whole() {
+        let layout = layout("ab日本", 3, WrapMode::Char);
+        assert_eq!(cells(&layout), [(0, 0, 1), (0, 1, 1), (1, 0, 2), (2, 0, 2)]);
+        assert_eq!(layout.rows(), 3);
+        assert_eq!(layout.cursor_cell(3), (2, 0));
+        assert_eq!(layout.cursor_cell(4), (2, 2));
+    }
+
+    #[test]
+    fn a_full_last_row_puts_the_cursor_on_the_next() {
+        for wrap in [WrapMode::Char, WrapMode::Word] {
+            let full = layout("abcdef", 3, wrap);
+            assert_eq!(full.rows(), 3);
+            assert_eq!(full.cursor_cell(5), (1, 2));
+            assert_eq!(full.cursor_cell(6), (2, 0));
+        }
+        let unwrapped = layout("abc", 3, WrapMode::Off);
+        assert_eq!(unwrapped.rows(), 1);
+        assert_eq!(unwrapped.cursor_cell(3), (0, 3));
+    }
+
+    #[test]
+    fn grapheme_clusters_are_placed_as_one() {
+        // "e" with a combining acute, then a family emoji joined by ZWJs
+        let line = "e\u{301}👨\u{200d}👩\u{200d}👧x";
+        let layout = layout(line, 80, WrapMode::Word);
+        assert_eq!(cells(&layout), [(0, 0, 1), (0, 1, 2), (0, 3, 1)]);
+        assert_eq!(layout.placed[2].chars, 7);
+        // A cursor inside a cluster sits on it
+        assert_eq!(layout.cursor_cell(1), (0, 0));
+        assert_eq!(layout.cursor_cell(4), (0, 1));
+        assert_eq!(layout.width(), 4);
+    }
+
+    #[test]
+    fn tabs_expand_to_tab_stops() {
+        let layout = layout("a\tb\t", 80, WrapMode::Off);
+        assert_eq!(cells(&layout), [(0, 0, 1), (0, 1, 3), (0, 4, 1), (0, 5, 3)]);
+        assert_eq!(LineLayout::cell_at_col("a\tb", 2, 4), 4);
+        assert_eq!(LineLayout::col_at_cell("a\tb", 2, 4), 1);
+    }
+
+    #[test]
+    fn word_wrap_moves_whole_words() {
+        let words = layout("foo bar baz", 9, WrapMode::Word);
+        let rows: Vec<usize> = words.placed.iter().map(|p| p.row).collect();
+        assert_eq!(rows, [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1]);
+        // Words longer than a row break anywhere
+        assert_eq!(layout("abcdefgh", 3, WrapMode::Word).rows(), 3);
+    }
+
+    #[test]
+    fn display_columns_count_wide_characters_twice() {
+        assert_eq!(LineLayout::cell_at_col("日本語", 2, 4), 4);
+        assert_eq!(LineLayout::col_at_cell("日本語", 3, 4), 1);
+        assert_eq!(LineLayout::col_at_cell("日本語", 9, 4), 3);
+    }
+
+    #[test]
+    fn layout_stops_early() {
+        let line = "abcdef".repeat(1000);
+        let unwrapped = LineLayout::new(&line, 10, 4, WrapMode::Off, Stop::Cell(0, 10));
+        assert_eq!(unwrapped.placed.len(), 10);
+        assert_eq!(unwrapped.bytes(), 10);
+
+        let wrapped = LineLayout::new(&line, 10, 4, WrapMode::Char, Stop::Cell(2, 0));
+        assert_eq!(wrapped.rows(), 2);
+        assert_eq!(wrapped.placed.len(), 20);
+
+        let to_cursor = LineLayout::new(&line, 10, 4, WrapMode::Char, Stop::Char(25));
+        assert_eq!(to_cursor.cursor_cell(25), (2, 5));
+        assert_eq!(to_cursor.placed.len(), 26);
+    }
+}